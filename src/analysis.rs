@@ -0,0 +1,401 @@
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+use crate::controller::{Controllers, InputSource};
+use crate::fen::{START_FEN, parse_fen};
+use crate::hud;
+use crate::pgn::move_to_san;
+use crate::rules::{GameState, LogicChessPiece, PieceColor, PositionEdited, SharedData, find_uci_move, make_move};
+use crate::search::{DEFAULT_HASH_MB, MATE_BOUND, MATE_SCORE, MAX_DEPTH, Searcher};
+
+#[derive(Clone)]
+pub struct AnalysisConfig {
+    // The search goes one ply deeper at a time up to this depth, or until the position changes when None
+    pub max_depth: Option<u32>,
+    pub lines: usize,
+    // Size of the analysis' transposition table in MB
+    pub hash_size: usize,
+    // Whether analysis is on at startup, A switches it on and off
+    pub enabled: bool
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            max_depth: None,
+            lines: 3,
+            hash_size: DEFAULT_HASH_MB,
+            enabled: false
+        }
+    }
+}
+
+pub struct AnalysisLine {
+    // From White's point of view
+    pub score: i32,
+    pub moves: Vec<String>
+}
+
+pub struct AnalysisResult {
+    pub depth: u32,
+    pub nodes: u64,
+    pub nps: u64,
+    pub lines: Vec<AnalysisLine>
+}
+
+type Position = (Vec<Vec<Option<LogicChessPiece>>>, Vec<Vec<bool>>, PieceColor);
+
+// The result of the deepest finished search, for `position`
+#[derive(Default)]
+pub struct Analysis {
+    pub enabled: bool,
+    pub result: Option<AnalysisResult>,
+    position: Option<Position>,
+    running: Option<(Arc<AtomicBool>, Mutex<Receiver<AnalysisResult>>)>,
+    // One searcher for every position, made when the analysis first runs, so the table carries over from move to move
+    searcher: Option<Arc<Mutex<Searcher>>>,
+    // The moves stepped back over, the next one last, and the start and length of the history they follow.
+    // They're dropped once the history changes some other way, like a move played instead.
+    undone: Vec<String>,
+    stepped: Option<(Option<String>, usize)>
+}
+
+impl Analysis {
+    fn stop(&mut self) {
+        if let Some((stop, _)) = self.running.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct EvalBar;
+pub struct EvalFill;
+pub struct AnalysisText(usize);
+
+pub fn format_score(score: i32) -> String {
+    if score.abs() > MATE_BOUND {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        format!("{}#{}", if score > 0 { "" } else { "-" }, moves)
+    } else {
+        format!("{:+.2}", score as f32 / 100.)
+    }
+}
+
+// White's share of the bar, a pawn up is about 64%
+fn bar_share(score: i32) -> f32 {
+    if score.abs() > MATE_BOUND {
+        return if score > 0 { 1. } else { 0. };
+    }
+
+    1. / (1. + 10f32.powf(-score as f32 / 400.))
+}
+
+// Deepens the search one ply at a time and sends every finished depth, until it's told to stop.
+// The search of the last position is stopped first, the new one waits for it to give the searcher back.
+fn start(position: Position, config: &AnalysisConfig, searcher: Arc<Mutex<Searcher>>, tasks: &TaskPool)
+         -> (Arc<AtomicBool>, Mutex<Receiver<AnalysisResult>>) {
+    let (sender, receiver) = channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let (max_depth, lines) = (config.max_depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH), config.lines);
+
+    tasks.spawn(async move {
+        let (board, initial_pos, color) = position;
+        let mut searcher = searcher.lock().unwrap();
+
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+
+        searcher.stop = Some(stopped);
+
+        // The table keeps what the shallower searches found, so every depth starts from their best lines
+        for depth in 1..=max_depth {
+            let found = searcher.analyse(&board, &initial_pos, color, depth, lines);
+
+            if searcher.stopped() {
+                return;
+            }
+
+            let (nodes, nps) = found.first().map_or((0, 0), |info| (info.nodes, info.nps));
+            let lines = found.into_iter()
+                .map(|info| {
+                    let (score, pv) = (info.score, info.pv);
+                    let (mut board, mut initial_pos) = (board.clone(), initial_pos.clone());
+                    let moves = pv.iter()
+                        .map(|chess_move| {
+                            let san = move_to_san(&board, &initial_pos, chess_move);
+                            make_move(&mut board, &mut initial_pos, chess_move);
+                            san
+                        })
+                        .collect();
+
+                    AnalysisLine { score: if let PieceColor::White = color { score } else { -score }, moves }
+                })
+                .collect();
+
+            if sender.send(AnalysisResult { depth, nodes, nps, lines }).is_err() {
+                return;
+            }
+        }
+    }).detach();
+
+    (stop, Mutex::new(receiver))
+}
+
+fn spawn_display(commands: &mut Commands, asset_server: &AssetServer, materials: &mut Assets<ColorMaterial>,
+                 config: &AnalysisConfig) {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(10.),
+                top: Val::Percent(20.),
+                ..Default::default()
+            },
+            size: Size::new(Val::Px(20.), Val::Percent(60.)),
+            ..Default::default()
+        },
+        material: materials.add(Color::rgb(0.1, 0.1, 0.1).into()),
+        ..Default::default()
+    })
+        .with(EvalBar)
+        .with_children(|parent| {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(0.),
+                        bottom: Val::Px(0.),
+                        ..Default::default()
+                    },
+                    size: Size::new(Val::Percent(100.), Val::Percent(50.)),
+                    ..Default::default()
+                },
+                material: materials.add(Color::rgb(0.95, 0.95, 0.95).into()),
+                ..Default::default()
+            })
+                .with(EvalFill);
+        });
+
+    // The depth comes first, then one line per move, all above the keyboard entry
+    for i in 0..=config.lines {
+        commands.spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(40.),
+                    bottom: Val::Px(40. + (config.lines - i) as f32 * 24.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load(hud::FONT),
+                style: TextStyle {
+                    font_size: 20.,
+                    color: Color::WHITE,
+                    ..Default::default()
+                }
+            },
+            ..Default::default()
+        })
+            .with(AnalysisText(i));
+    }
+}
+
+pub fn setup(config: Res<AnalysisConfig>, mut analysis: ResMut<Analysis>) {
+    analysis.enabled = config.enabled;
+}
+
+// A toggles the analysis, and leaving the editor with Shift+Enter turns it on
+pub fn analysis_toggle_system(commands: &mut Commands, keyboard_input: Res<Input<KeyCode>>,
+                              mut edit_reader: Local<EventReader<PositionEdited>>,
+                              edits: Res<Events<PositionEdited>>,
+                              config: Res<AnalysisConfig>, asset_server: Res<AssetServer>,
+                              mut materials: ResMut<Assets<ColorMaterial>>, mut analysis: ResMut<Analysis>,
+                              bar_query: Query<Entity, With<EvalBar>>,
+                              text_query: Query<Entity, With<AnalysisText>>) {
+    if keyboard_input.just_pressed(KeyCode::A) {
+        analysis.enabled = !analysis.enabled;
+        println!("Analysis: {}", if analysis.enabled { "on" } else { "off" });
+    }
+
+    if edit_reader.iter(&edits).any(|edited| edited.analyse) && !analysis.enabled {
+        analysis.enabled = true;
+        println!("Analysis: on");
+    }
+
+    let shown = bar_query.iter().next().is_some();
+
+    if analysis.enabled && !shown {
+        spawn_display(commands, &asset_server, &mut materials, &config);
+    } else if !analysis.enabled && shown {
+        for entity in bar_query.iter() {
+            commands.despawn_recursive(entity);
+        }
+
+        for entity in text_query.iter() {
+            commands.despawn(entity);
+        }
+
+        analysis.stop();
+        analysis.position = None;
+        analysis.result = None;
+    }
+}
+
+// The position after `history`, played from `start`, a FEN or None for the starting position
+fn replay(start: Option<&str>, history: &[String]) -> Result<Position, String> {
+    let position = parse_fen(start.unwrap_or(START_FEN)).map_err(|e| format!("bad start position: {}", e))?;
+    let (mut board, mut initial_pos, mut color) = (position.board, position.initial_pos, position.current_move);
+
+    for uci in history.iter() {
+        let chess_move = find_uci_move(&board, &initial_pos, color, uci)
+            .ok_or(format!("{} doesn't follow from the starting position", uci))?;
+
+        make_move(&mut board, &mut initial_pos, &chess_move);
+        color = match color {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White
+        };
+    }
+
+    Ok((board, initial_pos, color))
+}
+
+// Comma steps back through the moves and period forward again, once the game is over or while nobody but the
+// players at this machine is playing, so neither an engine nor a network player is taken back
+pub fn history_step_system(keyboard_input: Res<Input<KeyCode>>, controllers: Res<Controllers>,
+                           mut analysis: ResMut<Analysis>, mut shared_data: ResMut<SharedData>) {
+    let back = keyboard_input.just_pressed(KeyCode::Comma);
+    let forward = keyboard_input.just_pressed(KeyCode::Period);
+
+    if !analysis.enabled || !(back || forward) {
+        return;
+    }
+
+    let local = [PieceColor::White, PieceColor::Black].iter().all(|color| {
+        let controller = controllers.get(*color);
+        controller.accepts(InputSource::Mouse) || controller.accepts(InputSource::Keyboard)
+    });
+    let steppable = match shared_data.game_state {
+        GameState::GameOver => true,
+        GameState::WaitingForSelect => local,
+        _ => false
+    };
+
+    if !steppable || shared_data.local_color.is_some() {
+        return;
+    }
+
+    if analysis.stepped != Some((shared_data.start.clone(), shared_data.move_history.len())) {
+        analysis.undone = Vec::new();
+    }
+
+    let mut history = shared_data.move_history.clone();
+
+    if back {
+        match history.pop() {
+            Some(uci) => analysis.undone.push(uci),
+            None => return
+        }
+    } else {
+        match analysis.undone.pop() {
+            Some(uci) => history.push(uci),
+            None => return
+        }
+    }
+
+    match replay(shared_data.start.as_deref(), &history) {
+        Ok((board, initial_pos, current_move)) => {
+            println!("Analysis: after {} of {} plies", history.len(), history.len() + analysis.undone.len());
+            shared_data.board = board;
+            shared_data.initial_pos = initial_pos;
+            shared_data.current_move = current_move;
+            shared_data.move_history = history;
+            analysis.stepped = Some((shared_data.start.clone(), shared_data.move_history.len()));
+        },
+        Err(e) => {
+            println!("Analysis: {}", e);
+            analysis.undone = Vec::new();
+        }
+    }
+}
+
+// Whatever changes the board, a move, the editor or a loaded game, starts the analysis again
+pub fn analysis_system(config: Res<AnalysisConfig>, shared_data: Res<SharedData>, tasks: Res<AsyncComputeTaskPool>,
+                       mut analysis: ResMut<Analysis>,
+                       mut fill_query: Query<&mut Style, With<EvalFill>>,
+                       mut text_query: Query<(&mut Text, &AnalysisText)>) {
+    if !analysis.enabled {
+        return;
+    }
+
+    let position = (shared_data.board.clone(), shared_data.initial_pos.clone(), shared_data.current_move);
+
+    if analysis.position.as_ref() != Some(&position) {
+        let searcher = analysis.searcher.get_or_insert_with(|| {
+            Arc::new(Mutex::new(Searcher::new(config.hash_size)))
+        }).clone();
+
+        analysis.stop();
+        analysis.result = None;
+        analysis.running = Some(start(position.clone(), &config, searcher, &tasks));
+        analysis.position = Some(position);
+    }
+
+    let latest = match &analysis.running {
+        Some((_, receiver)) => receiver.lock().unwrap().try_iter().last(),
+        None => None
+    };
+
+    if latest.is_some() {
+        analysis.result = latest;
+    }
+
+    let best = analysis.result.as_ref().and_then(|result| result.lines.first()).map(|line| line.score);
+    let share = match best {
+        Some(score) => bar_share(score),
+        // Mated or stalemated, or nothing found yet
+        None => 0.5
+    };
+
+    for mut style in fill_query.iter_mut() {
+        style.size.height = Val::Percent(share * 100.);
+    }
+
+    for (mut text, line) in text_query.iter_mut() {
+        text.value = match (&analysis.result, line.0) {
+            (None, 0) => "Analysing...".to_string(),
+            (Some(result), 0) if result.lines.is_empty() => "No legal moves".to_string(),
+            (Some(result), 0) => format!("Depth {}  {} nodes  {} kn/s", result.depth, result.nodes,
+                                         result.nps / 1000),
+            (Some(result), i) => match result.lines.get(i - 1) {
+                Some(line) => format!("{:>6}  {}", format_score(line.score), line.moves.join(" ")),
+                None => String::new()
+            },
+            _ => String::new()
+        };
+    }
+}
+
+#[derive(Default)]
+pub struct AnalysisPlugin {
+    pub config: AnalysisConfig
+}
+
+impl Plugin for AnalysisPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(Analysis::default())
+            .add_startup_system(setup.system())
+            .add_system(analysis_toggle_system.system())
+            .add_system(history_step_system.system())
+            .add_system(analysis_system.system());
+    }
+}
@@ -0,0 +1,483 @@
+use bevy::prelude::*;
+use bevy_mod_picking::*;
+use serde::Deserialize;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::rules::{BoardPosition, PieceColor, PieceType, SharedData, piece_letter, position_to_string};
+
+pub const SYNC_STAGE: &str = "sync_pieces";
+
+pub struct ChessPiece;
+pub struct ChessBoard;
+
+// Where a piece's mesh comes from and how it's placed: `mesh` is the index of the mesh in the glTF
+// file, `rotation` turns it around the vertical axis in degrees and `height` lifts it off the board
+#[derive(Deserialize, Clone, Debug)]
+pub struct PieceModel {
+    pub asset: String,
+    pub mesh: u32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub height: f32
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+// A piece set manifest, a RON file with a model for every piece type
+#[derive(Deserialize, Clone, Debug)]
+pub struct PieceSet {
+    pub king: PieceModel,
+    pub queen: PieceModel,
+    pub rook: PieceModel,
+    pub bishop: PieceModel,
+    pub knight: PieceModel,
+    pub pawn: PieceModel
+}
+
+impl PieceSet {
+    pub fn load(path: &str) -> Result<PieceSet, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+
+        ron::de::from_str(&text).map_err(|e| format!("can't parse {}: {}", path, e))
+    }
+
+    pub fn model(&self, piece_type: PieceType) -> &PieceModel {
+        match piece_type {
+            PieceType::King => &self.king,
+            PieceType::Queen => &self.queen,
+            PieceType::Rook => &self.rook,
+            PieceType::Bishop => &self.bishop,
+            PieceType::Knight => &self.knight,
+            PieceType::Pawn => &self.pawn
+        }
+    }
+}
+
+// The set the game always had, used when the manifest can't be loaded
+impl Default for PieceSet {
+    fn default() -> Self {
+        let model = |mesh: u32, rotation: f32| PieceModel {
+            asset: "models/pawns.glb".to_string(),
+            mesh,
+            scale: 1.,
+            rotation,
+            height: 0.
+        };
+
+        PieceSet {
+            king: model(4, 0.),
+            queen: model(5, 0.),
+            rook: model(2, 0.),
+            bishop: model(1, 0.),
+            knight: model(3, -90.),
+            pawn: model(0, 0.)
+        }
+    }
+}
+
+pub struct Meshes {
+    pub king: Handle<Mesh>,
+    pub queen: Handle<Mesh>,
+    pub rook: Handle<Mesh>,
+    pub bishop: Handle<Mesh>,
+    pub knight: Handle<Mesh>,
+    pub pawn: Handle<Mesh>,
+    pub set: PieceSet
+}
+
+impl Meshes {
+    // The transform of a piece standing at `translation`, facing the other side of the board
+    pub fn piece_transform(&self, piece_type: PieceType, color: PieceColor, translation: Vec3) -> Transform {
+        let model = self.set.model(piece_type);
+        let facing = match color {
+            PieceColor::White => 0.0,
+            PieceColor::Black => PI
+        };
+
+        Transform {
+            translation: translation + Vec3::new(0.0, model.height, 0.0),
+            rotation: Quat::from_rotation_y(facing + model.rotation.to_radians()),
+            scale: Vec3::splat(model.scale)
+        }
+    }
+}
+
+// All the pieces of a colour share one material, so a new theme only has to change these
+pub struct PieceMaterials {
+    pub white: Handle<StandardMaterial>,
+    pub black: Handle<StandardMaterial>,
+    // Selected pieces wear this one instead of their own
+    pub selected: Handle<StandardMaterial>
+}
+
+impl PieceMaterials {
+    pub fn get(&self, color: PieceColor) -> Handle<StandardMaterial> {
+        match color {
+            PieceColor::White => self.white.clone(),
+            PieceColor::Black => self.black.clone()
+        }
+    }
+}
+
+pub struct BoardMaterial(pub Handle<StandardMaterial>);
+
+// The textures are the starting look, a theme can replace them later
+#[derive(Clone)]
+pub struct BoardRenderConfig {
+    pub board_texture: String,
+    pub white_texture: String,
+    pub black_texture: String,
+    pub selection_color: Color,
+    // Path of the piece set manifest
+    pub piece_set: String,
+    // The light stays where it is when the camera moves
+    pub light_position: Vec3,
+    pub check_sync: bool
+}
+
+impl Default for BoardRenderConfig {
+    fn default() -> Self {
+        BoardRenderConfig {
+            board_texture: "textures/board.png".to_string(),
+            white_texture: "textures/cc0textures.com/Metal024_1K_Color.png".to_string(),
+            black_texture: "textures/cc0textures.com/Rust004_1K_Color.png".to_string(),
+            selection_color: Color::rgb(0.0, 0.0, 1.0),
+            piece_set: "assets/models/pawns.ron".to_string(),
+            light_position: Vec3::new(0.0, 12.0, 0.0),
+            check_sync: cfg!(debug_assertions)
+        }
+    }
+}
+
+pub fn board_to_global(position: BoardPosition) -> Vec3 {
+    Vec3::new(position.x as f32 - 3.5,
+              0.0,
+              -(position.y as f32 - 3.5))
+}
+
+// The square under a point on the board, None when it's off the board
+pub fn global_to_board(position: Vec3) -> Option<BoardPosition> {
+    let (x, y) = ((position.x + 4.).floor(), (-position.z + 4.).floor());
+
+    if !(0. ..8.).contains(&x) || !(0. ..8.).contains(&y) {
+        return None;
+    }
+
+    Some(BoardPosition { x: x as u8, y: y as u8 })
+}
+
+fn load_meshes(asset_server: &AssetServer, set: PieceSet) -> Meshes {
+    let mesh = |piece_type: PieceType| {
+        let model = set.model(piece_type);
+        asset_server.load(format!("{}#Mesh{}/Primitive0", model.asset, model.mesh).as_str())
+    };
+
+    Meshes {
+        king: mesh(PieceType::King),
+        queen: mesh(PieceType::Queen),
+        rook: mesh(PieceType::Rook),
+        bishop: mesh(PieceType::Bishop),
+        knight: mesh(PieceType::Knight),
+        pawn: mesh(PieceType::Pawn),
+        set
+    }
+}
+
+// The manifests in the directory of the configured one, sorted
+pub fn piece_set_paths(config: &BoardRenderConfig) -> Vec<String> {
+    let directory = Path::new(&config.piece_set).parent().unwrap_or_else(|| Path::new("."));
+    let mut paths: Vec<String> = fs::read_dir(directory).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+
+    paths.sort();
+    paths
+}
+
+pub fn setup(
+    commands: &mut Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>, config: Res<BoardRenderConfig>) {
+
+    let set = PieceSet::load(&config.piece_set).unwrap_or_else(|e| {
+        println!("Piece set: {}, using the default one", e);
+        PieceSet::default()
+    });
+    let texture_material = |path: &str| StandardMaterial {
+        albedo_texture: Some(asset_server.load(path)),
+        ..Default::default()
+    };
+    let board_material = materials.add(texture_material(&config.board_texture));
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Plane { size: 8.0 })),
+        material: board_material.clone(),
+        ..Default::default()
+    })
+        .with(PickableMesh::default())
+        .with(InteractableMesh::default())
+        .with(ChessBoard)
+        .spawn(LightBundle {
+            transform: Transform::from_translation(config.light_position),
+            light: Light {
+                depth: (0.0..1000.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert_resource(load_meshes(&asset_server, set))
+        .insert_resource(PieceMaterials {
+            white: materials.add(texture_material(&config.white_texture)),
+            black: materials.add(texture_material(&config.black_texture)),
+            selected: materials.add(config.selection_color.into())
+        })
+        .insert_resource(BoardMaterial(board_material));
+}
+
+pub fn spawn_piece(commands: &mut Commands, piece_materials: &PieceMaterials, meshes: &Meshes,
+                   piece_type: PieceType, color: PieceColor, position: BoardPosition) {
+    commands.spawn(PbrBundle {
+        mesh: piece_mesh(meshes, piece_type),
+        material: piece_materials.get(color),
+        transform: meshes.piece_transform(piece_type, color, board_to_global(position)),
+        ..Default::default()})
+        .with(PickableMesh::default())
+        .with(InteractableMesh::default())
+        .with(piece_type)
+        .with(color)
+        .with(position)
+        .with(ChessPiece);
+}
+
+pub fn piece_mesh(meshes: &Meshes, piece_type: PieceType) -> Handle<Mesh> {
+    match piece_type {
+        PieceType::King => meshes.king.clone(),
+        PieceType::Queen => meshes.queen.clone(),
+        PieceType::Rook => meshes.rook.clone(),
+        PieceType::Bishop => meshes.bishop.clone(),
+        PieceType::Knight => meshes.knight.clone(),
+        PieceType::Pawn => meshes.pawn.clone()
+    }
+}
+
+// M switches to the next piece set, the pieces on the board and the promotion selector take its meshes at once
+pub fn piece_set_system(keyboard_input: Res<Input<KeyCode>>, asset_server: Res<AssetServer>,
+                        config: Res<BoardRenderConfig>, mut meshes: ResMut<Meshes>,
+                        mut models: Query<(&PieceType, &mut Handle<Mesh>)>,
+                        mut pieces: Query<(&PieceType, &PieceColor, &BoardPosition, &mut Transform), With<ChessPiece>>,
+                        mut current: Local<Option<String>>) {
+    if !keyboard_input.just_pressed(KeyCode::M) {
+        return;
+    }
+
+    let current = current.get_or_insert_with(|| config.piece_set.clone());
+    let paths = piece_set_paths(&config);
+
+    *current = match paths.iter().position(|path| path == current) {
+        Some(index) => paths[(index + 1) % paths.len()].clone(),
+        None => match paths.first() {
+            Some(first) => first.clone(),
+            None => {
+                println!("Piece set: no manifests next to {}", config.piece_set);
+                return;
+            }
+        }
+    };
+
+    // A broken manifest keeps the old set, the next press moves on
+    let set = match PieceSet::load(current) {
+        Ok(set) => set,
+        Err(e) => {
+            println!("Piece set: {}", e);
+            return;
+        }
+    };
+
+    println!("Piece set: {}", current);
+    *meshes = load_meshes(&asset_server, set);
+
+    for (piece_type, mut mesh) in models.iter_mut() {
+        *mesh = piece_mesh(&meshes, *piece_type);
+    }
+
+    for (piece_type, piece_color, board_position, mut transform) in pieces.iter_mut() {
+        *transform = meshes.piece_transform(*piece_type, *piece_color, board_to_global(*board_position));
+    }
+}
+
+fn distance(a: BoardPosition, b: BoardPosition) -> i32 {
+    (a.x as i32 - b.x as i32).abs() + (a.y as i32 - b.y as i32).abs()
+}
+
+// The logic board is authoritative, the piece entities are reconciled with it every frame:
+// pieces that left their square are moved to a square that needs the same piece, promoted pawns get
+// their mesh swapped, and whatever is still missing or left over is spawned or despawned
+pub fn piece_sync_system(
+    commands: &mut Commands,
+    mut query: Query<(Entity, &mut Transform, &mut BoardPosition, &mut PieceType, &mut Handle<Mesh>, &PieceColor),
+                     With<ChessPiece>>,
+    piece_materials: Res<PieceMaterials>, meshes: Res<Meshes>, shared_data: Res<SharedData>) {
+
+    let board = &shared_data.board;
+    let mut satisfied = vec![vec![false; 8]; 8];
+    let mut strays = Vec::new();
+
+    for (entity, _, board_position, piece_type, _, piece_color) in query.iter_mut() {
+        let (x, y) = (board_position.x as usize, board_position.y as usize);
+        let in_place = match board[y][x] {
+            Some(piece) => piece.piece_type == *piece_type && piece.piece_color == *piece_color,
+            None => false
+        };
+
+        if in_place && !satisfied[y][x] {
+            satisfied[y][x] = true;
+        } else {
+            strays.push((entity, *board_position, *piece_type, *piece_color));
+        }
+    }
+
+    for (i, row) in board.iter().enumerate() {
+        for (j, field) in row.iter().enumerate() {
+            let piece = match field {
+                Some(piece) if !satisfied[i][j] => *piece,
+                _ => continue
+            };
+            let position = BoardPosition { x: j as u8, y: i as u8 };
+
+            // The closest piece of the same kind is taken first, then the closest of the same colour
+            let nearest = |same_type: bool| strays.iter().enumerate()
+                .filter(|(_, (_, _, piece_type, piece_color))| *piece_color == piece.piece_color
+                    && (!same_type || *piece_type == piece.piece_type))
+                .min_by_key(|(_, (_, stray_position, _, _))| distance(*stray_position, position))
+                .map(|(index, _)| index);
+
+            match nearest(true).or_else(|| nearest(false)) {
+                Some(index) => {
+                    let (entity, _, _, _) = strays.remove(index);
+                    let (_, mut transform, mut board_position, mut piece_type, mut mesh, _) =
+                        query.get_mut(entity).unwrap();
+
+                    *board_position = position;
+                    *transform = meshes.piece_transform(piece.piece_type, piece.piece_color, board_to_global(position));
+
+                    if *piece_type != piece.piece_type {
+                        *piece_type = piece.piece_type;
+                        *mesh = piece_mesh(&meshes, piece.piece_type);
+                    }
+                },
+                None => spawn_piece(commands, &piece_materials, &meshes,
+                                    piece.piece_type, piece.piece_color, position)
+            }
+        }
+    }
+
+    for (entity, _, _, _) in strays {
+        commands.despawn(entity);
+    }
+}
+
+// Reports every difference between the logic board and the piece entities, used to catch anything
+// that moves the entities behind the sync system's back
+pub fn sync_check_system(query: Query<(&BoardPosition, &PieceType, &PieceColor), With<ChessPiece>>,
+                         config: Res<BoardRenderConfig>, shared_data: Res<SharedData>,
+                         mut last_report: Local<String>) {
+    if !config.check_sync {
+        return;
+    }
+
+    let mut entities = vec![vec![Vec::new(); 8]; 8];
+
+    for (board_position, piece_type, piece_color) in query.iter() {
+        entities[board_position.y as usize][board_position.x as usize].push((*piece_type, *piece_color));
+    }
+
+    let describe = |piece_type: PieceType, piece_color: PieceColor| {
+        let color = if let PieceColor::White = piece_color { 'w' } else { 'b' };
+        format!("{}{}", color, piece_letter(piece_type))
+    };
+    let mut report = String::new();
+
+    for (i, row) in shared_data.board.iter().enumerate() {
+        for (j, field) in row.iter().enumerate() {
+            let expected: Vec<String> = field.iter()
+                .map(|piece| describe(piece.piece_type, piece.piece_color))
+                .collect();
+            let found: Vec<String> = entities[i][j].iter()
+                .map(|(piece_type, piece_color)| describe(*piece_type, *piece_color))
+                .collect();
+
+            if expected != found {
+                report += &format!(" {}: board [{}], entities [{}];",
+                                   position_to_string(BoardPosition { x: j as u8, y: i as u8 }),
+                                   expected.join(" "), found.join(" "));
+            }
+        }
+    }
+
+    if report != *last_report {
+        if !report.is_empty() {
+            println!("Board sync mismatch:{}", report);
+        }
+
+        *last_report = report;
+    }
+}
+
+#[derive(Default)]
+pub struct ChessBoardRenderPlugin {
+    pub config: BoardRenderConfig
+}
+
+impl Plugin for ChessBoardRenderPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_startup_system(setup.system())
+            .add_system(piece_set_system.system())
+            .add_stage_after(stage::UPDATE, SYNC_STAGE, SystemStage::parallel())
+            .add_system_to_stage(SYNC_STAGE, piece_sync_system.system())
+            .add_system_to_stage(stage::POST_UPDATE, sync_check_system.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_manifests_load() {
+        let paths = piece_set_paths(&BoardRenderConfig::default());
+
+        assert!(paths.len() >= 2, "{:?}", paths);
+
+        for path in paths {
+            let set = PieceSet::load(&path).unwrap();
+
+            assert_eq!(set.model(PieceType::Knight).asset, "models/pawns.glb");
+        }
+    }
+
+    #[test]
+    fn optional_fields_default() {
+        let set = PieceSet::load("assets/models/pawns.ron").unwrap();
+        let knight = set.model(PieceType::Knight);
+
+        assert_eq!((knight.mesh, knight.scale, knight.rotation, knight.height), (3, 1., -90., 0.));
+
+        let tall = PieceSet::load("assets/models/pawns_tall.ron").unwrap();
+
+        assert_eq!(tall.model(PieceType::King).scale, 1.3);
+        assert_eq!(tall.model(PieceType::King).height, 0.);
+    }
+}
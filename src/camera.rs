@@ -0,0 +1,263 @@
+use bevy::prelude::*;
+use bevy::input::mouse::{MouseMotion, MouseButtonInput, MouseWheel};
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use std::f32::consts::PI;
+
+use crate::controller::{Controllers, InputSource};
+use crate::rules::{PieceColor, SharedData};
+
+#[derive(Clone)]
+pub struct OrbitCameraConfig {
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    pub rotate_sensitivity: f32,
+    pub zoom_sensitivity: f32,
+    // Arrow keys turn the camera by this many radians per second, Page Up/Down zoom by this much per second
+    pub key_rotate_speed: f32,
+    pub key_zoom_speed: f32,
+    // How quickly the camera closes in on where it's heading, higher is snappier
+    pub smoothing: f32,
+    // In hotseat games the camera turns to the side to move after every move
+    pub follow_side_to_move: bool
+}
+
+impl Default for OrbitCameraConfig {
+    fn default() -> Self {
+        OrbitCameraConfig {
+            min_distance: 9.,
+            max_distance: 20.,
+            min_pitch: 5. * PI / 180.,
+            max_pitch: PI / 2. - PI / 360.,
+            rotate_sensitivity: 1. / (16. * PI),
+            zoom_sensitivity: 0.8,
+            key_rotate_speed: PI / 2.,
+            key_zoom_speed: 8.,
+            smoothing: 8.,
+            follow_side_to_move: false
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CameraPreset {
+    WhiteView,
+    BlackView,
+    TopDown,
+    Side
+}
+
+// The camera sits at `rotation_angle` (pitch, yaw) and `distance` from the middle of the board
+// and moves smoothly towards the target ones
+pub struct OrbitCamera {
+    pub rotating: bool,
+    pub rotation_angle: Vec3,
+    pub distance: f32,
+    pub target_angle: Vec3,
+    pub target_distance: f32
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        let rotation_angle = Vec3::new(PI / 4., PI / 2., 0.);
+        let distance = (200.0_f32).sqrt();
+
+        OrbitCamera {
+            rotating: false,
+            rotation_angle,
+            distance,
+            target_angle: rotation_angle,
+            target_distance: distance
+        }
+    }
+}
+
+impl OrbitCamera {
+    // Presets keep the current distance, the top-down view looks at the board from White's side
+    pub fn go_to(&mut self, preset: CameraPreset, config: &OrbitCameraConfig) {
+        let (pitch, yaw) = match preset {
+            CameraPreset::WhiteView => (PI / 4., PI / 2.),
+            CameraPreset::BlackView => (PI / 4., 3. * PI / 2.),
+            CameraPreset::TopDown => (config.max_pitch, PI / 2.),
+            CameraPreset::Side => (PI / 6., 0.)
+        };
+
+        self.target_angle = Vec3::new(pitch, yaw, 0.);
+    }
+
+    fn clamp_target(&mut self, config: &OrbitCameraConfig) {
+        self.target_angle.x = self.target_angle.x.max(config.min_pitch).min(config.max_pitch);
+        self.target_angle.y = self.target_angle.y.rem_euclid(2. * PI);
+        self.target_distance = self.target_distance.max(config.min_distance).min(config.max_distance);
+    }
+}
+
+// Brings an angle difference into -PI..PI, so turning always takes the short way round
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2. * PI) - PI
+}
+
+pub fn setup(commands: &mut Commands) {
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_translation(Vec3::new(0.0, 10.0, 10.0))
+            .looking_at(Vec3::default(), Vec3::unit_y()),
+        ..Default::default()
+    });
+}
+
+pub fn camera_input_system(
+    evt_motion: Res<Events<MouseMotion>>,
+    mut evr_motion: Local<EventReader<MouseMotion>>,
+    evt_mousebtn: Res<Events<MouseButtonInput>>,
+    mut evr_mousebtn: Local<EventReader<MouseButtonInput>>,
+    evt_scroll: Res<Events<MouseWheel>>,
+    mut evr_scroll: Local<EventReader<MouseWheel>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+
+    mut config: ResMut<OrbitCameraConfig>,
+    mut orbit_camera: ResMut<OrbitCamera>) {
+
+    for e in evr_scroll.iter(&evt_scroll) {
+        orbit_camera.target_distance -= e.y * config.zoom_sensitivity;
+    }
+
+    if orbit_camera.rotating {
+        for e in evr_motion.iter(&evt_motion) {
+            orbit_camera.target_angle.x += e.delta.y * config.rotate_sensitivity;
+            orbit_camera.target_angle.y += e.delta.x * config.rotate_sensitivity;
+        }
+    }
+
+    for e in evr_mousebtn.iter(&evt_mousebtn) {
+
+        if e.button != MouseButton::Right {
+            continue;
+        }
+
+        orbit_camera.rotating = e.state.is_pressed();
+    }
+
+    let delta = time.delta_seconds();
+    let rotate = config.key_rotate_speed * delta;
+    let zoom = config.key_zoom_speed * delta;
+
+    if keyboard_input.pressed(KeyCode::Left) {
+        orbit_camera.target_angle.y -= rotate;
+    }
+
+    if keyboard_input.pressed(KeyCode::Right) {
+        orbit_camera.target_angle.y += rotate;
+    }
+
+    if keyboard_input.pressed(KeyCode::Up) {
+        orbit_camera.target_angle.x += rotate;
+    }
+
+    if keyboard_input.pressed(KeyCode::Down) {
+        orbit_camera.target_angle.x -= rotate;
+    }
+
+    if keyboard_input.pressed(KeyCode::PageUp) {
+        orbit_camera.target_distance -= zoom;
+    }
+
+    if keyboard_input.pressed(KeyCode::PageDown) {
+        orbit_camera.target_distance += zoom;
+    }
+
+    let presets = [(KeyCode::F1, CameraPreset::WhiteView), (KeyCode::F2, CameraPreset::BlackView),
+                   (KeyCode::F3, CameraPreset::TopDown), (KeyCode::F4, CameraPreset::Side)];
+
+    for (key, preset) in presets.iter() {
+        if keyboard_input.just_pressed(*key) {
+            orbit_camera.go_to(*preset, &config);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        config.follow_side_to_move = !config.follow_side_to_move;
+        println!("Camera follows the side to move: {}", if config.follow_side_to_move { "on" } else { "off" });
+    }
+
+    orbit_camera.clamp_target(&config);
+}
+
+// Only turns the camera when both players sit at this screen, a network player keeps their own side
+pub fn follow_side_system(config: Res<OrbitCameraConfig>, controllers: Res<Controllers>,
+                          shared_data: Res<SharedData>, mut orbit_camera: ResMut<OrbitCamera>,
+                          mut last_side: Local<Option<PieceColor>>) {
+    let side = shared_data.current_move;
+
+    if *last_side == Some(side) {
+        return;
+    }
+
+    *last_side = Some(side);
+
+    // Every player at this screen can type moves, engines and remote players can't
+    let hotseat = shared_data.local_color.is_none() && [PieceColor::White, PieceColor::Black].iter()
+        .all(|color| controllers.get(*color).accepts(InputSource::Keyboard));
+
+    if !config.follow_side_to_move || !hotseat {
+        return;
+    }
+
+    let preset = if let PieceColor::White = side { CameraPreset::WhiteView } else { CameraPreset::BlackView };
+    let pitch = orbit_camera.target_angle.x;
+
+    orbit_camera.go_to(preset, &config);
+    orbit_camera.target_angle.x = pitch;
+}
+
+pub fn camera_rotation_system(
+    time: Res<Time>,
+    config: Res<OrbitCameraConfig>,
+    mut orbit_camera: ResMut<OrbitCamera>,
+    mut query: Query<(&mut Transform, &Camera)>) {
+
+    // Covers the same share of the remaining way every second, whatever the frame rate
+    let step = 1. - (-config.smoothing * time.delta_seconds()).exp();
+    let yaw_offset = wrap_angle(orbit_camera.target_angle.y - orbit_camera.rotation_angle.y);
+
+    orbit_camera.rotation_angle.x += (orbit_camera.target_angle.x - orbit_camera.rotation_angle.x) * step;
+    orbit_camera.rotation_angle.y = (orbit_camera.rotation_angle.y + yaw_offset * step).rem_euclid(2. * PI);
+    orbit_camera.distance += (orbit_camera.target_distance - orbit_camera.distance) * step;
+
+    let radius = orbit_camera.distance;
+    let angle = orbit_camera.rotation_angle;
+
+    let tmp_transform = Transform::from_translation(Vec3::new(
+        radius * angle.x.cos() * angle.y.cos(),
+        radius * angle.x.sin(),
+        radius * angle.x.cos() * angle.y.sin()))
+        .looking_at(Vec3::default(), Vec3::unit_y());
+
+    // Only the 3D camera orbits, the UI camera stays where it is
+    for (mut transform, camera) in query.iter_mut() {
+        if camera.name.as_deref() != Some(CAMERA_3D) {
+            continue;
+        }
+
+        transform.translation = tmp_transform.translation;
+        transform.rotation = tmp_transform.rotation;
+    }
+}
+
+#[derive(Default)]
+pub struct OrbitCameraPlugin {
+    pub config: OrbitCameraConfig
+}
+
+impl Plugin for OrbitCameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(OrbitCamera::default())
+            .add_startup_system(setup.system())
+            .add_system(camera_input_system.system())
+            .add_system(follow_side_system.system())
+            .add_system(camera_rotation_system.system());
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use crate::rules::{LogicChessPiece, PieceType};
+use crate::search::piece_value;
+
+// Kept back on every move for the time it takes to pass the move on, so the flag doesn't fall while it's sent
+pub const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+// With less than this left the engine plays fast, losing on time is worse than a weaker move
+const EMERGENCY_TIME: Duration = Duration::from_secs(2);
+
+// How many more moves a game is expected to last when the time doesn't say, at the start and in the endgame
+const MOVES_LEFT_OPENING: f32 = 40.;
+const MOVES_LEFT_ENDGAME: f32 = 20.;
+
+// Queens, rooks, bishops and knights of both sides in the starting position
+const OPENING_MATERIAL: i32 = 2 * (900 + 2 * 500 + 2 * 330 + 2 * 320);
+
+// The time one player has left
+#[derive(Copy, Clone, Debug)]
+pub struct Clock {
+    pub remaining: Duration,
+    // Added after every move
+    pub increment: Duration,
+    // Moves until the next time control, the rest of the game is played on `remaining` when None
+    pub moves_to_go: Option<u32>
+}
+
+// The search doesn't start another depth after `soft` and stops wherever it is at `hard`
+#[derive(Copy, Clone, Debug)]
+pub struct Deadlines {
+    pub soft: Instant,
+    pub hard: Instant
+}
+
+// 0 in the opening, 1 when only kings and pawns are left
+pub fn game_phase(board: &[Vec<Option<LogicChessPiece>>]) -> f32 {
+    let material: i32 = board.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|field| field.as_ref())
+        .filter(|piece| piece.piece_type != PieceType::Pawn)
+        .map(|piece| piece_value(piece.piece_type))
+        .sum();
+
+    1. - (material as f32 / OPENING_MATERIAL as f32).min(1.)
+}
+
+impl Clock {
+    // Splits the time left over the moves still to be played. Most moves stop at the soft deadline, a search that
+    // is busy with a deeper line may go on up to the hard one, which never takes more than a share of the clock.
+    pub fn deadlines(&self, board: &[Vec<Option<LogicChessPiece>>], start: Instant) -> Deadlines {
+        let available = self.remaining.checked_sub(MOVE_OVERHEAD).unwrap_or_default();
+
+        let (soft, hard) = if available < EMERGENCY_TIME {
+            // Living on the increment, the clock is only touched a little
+            let soft = available / 40 + self.increment / 2;
+            (soft, (soft * 2).min(available / 4))
+        } else {
+            let moves_left = match self.moves_to_go {
+                Some(moves) => moves.max(1) as f32,
+                None => MOVES_LEFT_OPENING + (MOVES_LEFT_ENDGAME - MOVES_LEFT_OPENING) * game_phase(board)
+            };
+            // With the control only a move away, most of the clock can be spent
+            let max_share = if self.moves_to_go == Some(1) { 0.8 } else { 0.3 };
+
+            let soft = available.div_f32(moves_left) + self.increment * 3 / 4;
+            let hard = (soft * 4).min(available.mul_f32(max_share));
+
+            (soft, hard)
+        };
+
+        Deadlines {
+            soft: start + soft.min(hard),
+            hard: start + hard
+        }
+    }
+}
+
+// The clock settings of a game, both players start with `time`
+#[derive(Copy, Clone, Debug)]
+pub struct TimeControl {
+    pub time: Duration,
+    pub increment: Duration,
+    // The time is given again after this many moves of a player, as in classical time controls
+    pub moves_per_control: Option<u32>
+}
+
+impl TimeControl {
+    pub fn clock(&self) -> Clock {
+        Clock {
+            remaining: self.time,
+            increment: self.increment,
+            moves_to_go: self.moves_per_control
+        }
+    }
+
+    // Charges a move to the clock of the player who made it, `moves_made` counts that player's moves so far
+    pub fn after_move(&self, clock: &mut Clock, moves_made: u32, elapsed: Duration) {
+        clock.remaining = clock.remaining.checked_sub(elapsed).unwrap_or_default() + self.increment;
+
+        if let Some(moves) = self.moves_per_control {
+            if moves_made.is_multiple_of(moves) {
+                clock.remaining += self.time;
+            }
+
+            clock.moves_to_go = Some(moves - moves_made % moves);
+        }
+    }
+
+    // In PGN's notation, e.g. 300+2 or 40/5400
+    pub fn pgn_tag(&self) -> String {
+        let mut control = format!("{}", self.time.as_secs_f64());
+
+        if let Some(moves) = self.moves_per_control {
+            control = format!("{}/{}", moves, control);
+        }
+
+        if self.increment > Duration::from_secs(0) {
+            control += &format!("+{}", self.increment.as_secs_f64());
+        }
+
+        control
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn clock(remaining: u64, increment: u64, moves_to_go: Option<u32>) -> Clock {
+        Clock {
+            remaining: Duration::from_millis(remaining),
+            increment: Duration::from_millis(increment),
+            moves_to_go
+        }
+    }
+
+    // The deadlines in milliseconds after the start, rounded as the float maths may miss by a nanosecond
+    fn deadlines(clock: Clock, fen: &str) -> (u64, u64) {
+        let board = parse_fen(fen).unwrap().board;
+        let start = Instant::now();
+        let deadlines = clock.deadlines(&board, start);
+
+        let millis = |deadline: Instant| ((deadline - start).as_secs_f64() * 1000.).round() as u64;
+
+        (millis(deadlines.soft), millis(deadlines.hard))
+    }
+
+    #[test]
+    fn phases() {
+        assert_eq!(game_phase(&parse_fen(START).unwrap().board), 0.);
+        assert_eq!(game_phase(&parse_fen("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1").unwrap().board), 1.);
+    }
+
+    #[test]
+    fn time_is_split_over_the_moves_left() {
+        // 59.95 s over 40 moves in the opening and 20 in the endgame
+        assert_eq!(deadlines(clock(60_000, 0, None), START), (1499, 5995));
+        assert_eq!(deadlines(clock(60_000, 0, None), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"), (2997, 11990));
+        assert_eq!(deadlines(clock(60_000, 1000, None), START), (2249, 8995));
+        assert_eq!(deadlines(clock(60_000, 0, Some(10)), START), (5995, 17985));
+    }
+
+    #[test]
+    fn the_last_move_before_the_control_may_take_most_of_the_clock() {
+        assert_eq!(deadlines(clock(10_050, 0, Some(1)), START), (8000, 8000));
+    }
+
+    #[test]
+    fn little_time_is_spent_carefully() {
+        assert_eq!(deadlines(clock(1050, 0, None), START), (25, 50));
+        assert_eq!(deadlines(clock(1050, 100, None), START), (75, 150));
+        // Never later than the hard deadline
+        assert_eq!(deadlines(clock(1050, 1000, None), START), (250, 250));
+        assert_eq!(deadlines(clock(20, 0, None), START), (0, 0));
+    }
+
+    #[test]
+    fn time_controls() {
+        let control = TimeControl {
+            time: Duration::from_secs(300),
+            increment: Duration::from_secs(2),
+            moves_per_control: None
+        };
+        let mut clock = control.clock();
+
+        control.after_move(&mut clock, 1, Duration::from_secs(10));
+        assert_eq!(clock.remaining, Duration::from_secs(292));
+        assert!(clock.moves_to_go.is_none());
+        assert_eq!(control.pgn_tag(), "300+2");
+
+        let control = TimeControl {
+            time: Duration::from_secs(5400),
+            increment: Duration::from_secs(0),
+            moves_per_control: Some(40)
+        };
+        let mut clock = control.clock();
+
+        control.after_move(&mut clock, 39, Duration::from_secs(5000));
+        assert_eq!(clock.remaining, Duration::from_secs(400));
+        assert_eq!(clock.moves_to_go, Some(1));
+
+        control.after_move(&mut clock, 40, Duration::from_secs(100));
+        assert_eq!(clock.remaining, Duration::from_secs(5700));
+        assert_eq!(clock.moves_to_go, Some(40));
+        assert_eq!(control.pgn_tag(), "40/5400");
+    }
+}
@@ -0,0 +1,702 @@
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, TaskPool, TaskPoolBuilder};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::clock::Clock;
+use crate::pgn::find_move;
+use crate::rules::{ChessMove, GameState, LogicChessPiece, MoveRequested, PieceColor, SharedData,
+                   find_uci_move};
+use crate::search::{DEFAULT_HASH_MB, MATE_SCORE, MAX_DEPTH, Searcher};
+
+pub const DEFAULT_AI_DEPTH: u32 = 4;
+pub const DEFAULT_MOVETIME: u64 = 1000;
+pub const DEFAULT_THREADS: usize = 1;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum InputSource {
+    Mouse,
+    Keyboard,
+    Network
+}
+
+// A move made by a person or received from the peer, it only counts if the controller of its colour accepts it
+pub struct PlayerInput {
+    pub source: InputSource,
+    pub color: PieceColor,
+    pub request: MoveRequested
+}
+
+pub struct GameView<'a> {
+    pub board: &'a Vec<Vec<Option<LogicChessPiece>>>,
+    pub initial_pos: &'a Vec<Vec<bool>>,
+    pub current_move: PieceColor,
+    pub history: &'a Vec<String>,
+    // The FEN the moves in `history` start from, None for the starting position
+    pub start: Option<&'a str>,
+    // White's and Black's clocks, for games played against the clock
+    pub clocks: Option<[Clock; 2]>,
+    // Where slow controllers do their work inside the app and where the engine's helper threads search, they
+    // start threads of their own without them
+    pub tasks: Option<(&'a TaskPool, &'a TaskPool)>
+}
+
+impl<'a> GameView<'a> {
+    pub fn new(shared_data: &'a SharedData) -> Self {
+        GameView {
+            board: &shared_data.board,
+            initial_pos: &shared_data.initial_pos,
+            current_move: shared_data.current_move,
+            history: &shared_data.move_history,
+            start: shared_data.start.as_deref(),
+            clocks: None,
+            tasks: None
+        }
+    }
+}
+
+pub enum ControllerMove {
+    Waiting,
+    Ready(MoveRequested),
+    // The controller has no more moves to give, e.g. at the end of a script
+    Exhausted,
+    Failed(String)
+}
+
+pub trait PlayerController: Send + Sync {
+    fn description(&self) -> String;
+
+    // Whether inputs from `source` are moves of this player, also decides who may click the pieces
+    fn accepts(&self, _source: InputSource) -> bool {
+        false
+    }
+
+    fn offer(&mut self, _request: MoveRequested) {}
+
+    // Asked every frame while it's this player's turn, slow controllers answer Waiting until they're done
+    fn poll(&mut self, view: &GameView) -> ControllerMove;
+
+    // The engine's score of its last move in centipawns from its own side, for adjudicating engine games
+    fn score(&self) -> Option<i32> {
+        None
+    }
+}
+
+fn to_request(chess_move: &ChessMove) -> MoveRequested {
+    MoveRequested {
+        from: chess_move.from,
+        to: chess_move.to,
+        promotion: chess_move.promotion
+    }
+}
+
+// Moves made by a person are offered once they're complete
+pub struct HumanController {
+    keyboard: bool,
+    pending: Option<MoveRequested>
+}
+
+impl HumanController {
+    pub fn mouse() -> Self {
+        HumanController { keyboard: false, pending: None }
+    }
+
+    pub fn keyboard() -> Self {
+        HumanController { keyboard: true, pending: None }
+    }
+}
+
+impl PlayerController for HumanController {
+    fn description(&self) -> String {
+        if self.keyboard { "keyboard".to_string() } else { "mouse".to_string() }
+    }
+
+    // Mouse players can type their moves too, keyboard players only type
+    fn accepts(&self, source: InputSource) -> bool {
+        source == InputSource::Keyboard || (source == InputSource::Mouse && !self.keyboard)
+    }
+
+    fn offer(&mut self, request: MoveRequested) {
+        self.pending = Some(request);
+    }
+
+    fn poll(&mut self, _view: &GameView) -> ControllerMove {
+        match self.pending.take() {
+            Some(request) => ControllerMove::Ready(request),
+            None => ControllerMove::Waiting
+        }
+    }
+}
+
+// The peer's moves are checked by the network code first, so they're queued here as they arrive
+#[derive(Default)]
+pub struct NetworkController {
+    queue: VecDeque<MoveRequested>
+}
+
+impl PlayerController for NetworkController {
+    fn description(&self) -> String {
+        "network".to_string()
+    }
+
+    fn accepts(&self, source: InputSource) -> bool {
+        source == InputSource::Network
+    }
+
+    fn offer(&mut self, request: MoveRequested) {
+        self.queue.push_back(request);
+    }
+
+    fn poll(&mut self, _view: &GameView) -> ControllerMove {
+        match self.queue.pop_front() {
+            Some(request) => ControllerMove::Ready(request),
+            None => ControllerMove::Waiting
+        }
+    }
+}
+
+pub struct AiController {
+    // Without a clock the search goes DEFAULT_AI_DEPTH deep unless told otherwise, with one it goes as deep as the
+    // time allows
+    depth: Option<u32>,
+    threads: usize,
+    // Kept from move to move, so the table still holds the lines of the last search
+    searcher: Arc<Mutex<Searcher>>,
+    // The helper threads are set up at the first move, once it's known where they run
+    started: bool,
+    // The board the running search started from, a result for any other position is thrown away
+    searching: Option<(Vec<Vec<Option<LogicChessPiece>>>, Mutex<Receiver<Option<(ChessMove, i32)>>>)>,
+    score: Option<i32>
+}
+
+impl AiController {
+    pub fn new(depth: Option<u32>, threads: usize) -> Self {
+        AiController {
+            depth,
+            threads,
+            searcher: Arc::new(Mutex::new(Searcher::new(DEFAULT_HASH_MB))),
+            started: false,
+            searching: None,
+            score: None
+        }
+    }
+}
+
+impl PlayerController for AiController {
+    fn description(&self) -> String {
+        match self.depth {
+            Some(depth) => format!("ai:{}", depth),
+            None => "ai".to_string()
+        }
+    }
+
+    fn poll(&mut self, view: &GameView) -> ControllerMove {
+        if let Some((board, receiver)) = &self.searching {
+            let result = receiver.lock().unwrap().try_recv();
+
+            match result {
+                Ok(_) if board != view.board => self.searching = None,
+                Ok(Some((chess_move, score))) => {
+                    self.searching = None;
+                    self.score = Some(score);
+                    return ControllerMove::Ready(to_request(&chess_move));
+                },
+                Ok(None) => {
+                    self.searching = None;
+                    return ControllerMove::Failed("no legal moves".to_string());
+                },
+                Err(_) => return ControllerMove::Waiting
+            }
+        }
+
+        if !self.started {
+            self.searcher.lock().unwrap().set_threads(self.threads, view.tasks.map(|(_, search)| search.clone()));
+            self.started = true;
+        }
+
+        let (sender, receiver) = channel();
+        let (board, initial_pos, color) = (view.board.clone(), view.initial_pos.clone(), view.current_move);
+        let clock = view.clocks.map(|clocks| clocks[color as usize]);
+        let depth = match (self.depth, clock) {
+            (Some(depth), _) => depth,
+            (None, Some(_)) => MAX_DEPTH,
+            (None, None) => DEFAULT_AI_DEPTH
+        };
+        let searcher = self.searcher.clone();
+        let start = Instant::now();
+
+        let job = move || {
+            // The searcher is free again before the move is sent, so the next search never waits for this one
+            let best = {
+                let mut searcher = searcher.lock().unwrap();
+
+                searcher.deadlines = clock.map(|clock| clock.deadlines(&board, start));
+                searcher.iterate(&board, &initial_pos, color, depth, &[], &mut |_| ())
+                    .map(|info| (info.pv[0], info.score))
+            };
+            let _ = sender.send(best);
+        };
+
+        match view.tasks {
+            Some((tasks, _)) => tasks.spawn(async move { job() }).detach(),
+            None => {
+                thread::spawn(job);
+            }
+        }
+
+        self.searching = Some((view.board.clone(), Mutex::new(receiver)));
+
+        ControllerMove::Waiting
+    }
+
+    fn score(&self) -> Option<i32> {
+        self.score
+    }
+}
+
+struct UciEngine {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>
+}
+
+impl UciEngine {
+    fn start(path: &str, threads: usize) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("can't start engine {}: {}", path, e))?;
+
+        let input = child.stdin.take().ok_or("engine has no stdin")?;
+        let output = BufReader::new(child.stdout.take().ok_or("engine has no stdout")?);
+        let mut engine = UciEngine { child, input, output };
+
+        engine.send("uci")?;
+        engine.wait_for("uciok")?;
+
+        if threads != DEFAULT_THREADS {
+            engine.send(&format!("setoption name Threads value {}", threads))?;
+        }
+
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.input, "{}", command).map_err(|e| e.to_string())
+    }
+
+    fn wait_for(&mut self, prefix: &str) -> Result<String, String> {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+
+            match self.output.read_line(&mut line) {
+                Ok(0) => return Err("engine closed its output".to_string()),
+                Ok(_) if line.trim().starts_with(prefix) => return Ok(line.trim().to_string()),
+                Ok(_) => (),
+                Err(e) => return Err(e.to_string())
+            }
+        }
+    }
+
+    // Under a clock the engine is told the time left and manages it itself. The score of the last `info` line
+    // comes back with the move.
+    fn best_move(&mut self, request: &EngineRequest, movetime: u64) -> Result<(String, Option<i32>), String> {
+        let (start, history, clocks) = request;
+        let mut position = match start {
+            Some(fen) => format!("position fen {}", fen),
+            None => "position startpos".to_string()
+        };
+
+        if !history.is_empty() {
+            position += &format!(" moves {}", history.join(" "));
+        }
+
+        self.send(&position)?;
+
+        match clocks {
+            Some([white, black]) => {
+                let mut go = format!("go wtime {} btime {} winc {} binc {}",
+                                     white.remaining.as_millis(), black.remaining.as_millis(),
+                                     white.increment.as_millis(), black.increment.as_millis());
+
+                if let Some(moves) = white.moves_to_go.or(black.moves_to_go) {
+                    go += &format!(" movestogo {}", moves);
+                }
+
+                self.send(&go)?;
+            },
+            None => self.send(&format!("go movetime {}", movetime))?
+        }
+
+        let mut score = None;
+        let line = loop {
+            let line = self.wait_for("")?;
+
+            if line.starts_with("bestmove") {
+                break line;
+            }
+
+            if line.starts_with("info") {
+                score = parse_score(&line).or(score);
+            }
+        };
+
+        let uci = line.split_whitespace().nth(1)
+            .map(|uci| uci.to_string())
+            .ok_or(format!("malformed engine reply: {}", line))?;
+
+        Ok((uci, score))
+    }
+}
+
+// `score cp 35` or `score mate -3` of an `info` line, mates are scored like the built-in engine does
+fn parse_score(line: &str) -> Option<i32> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let i = words.iter().position(|word| *word == "score")?;
+    let value = words.get(i + 2)?.parse::<i32>().ok()?;
+
+    match words.get(i + 1) {
+        Some(&"cp") => Some(value),
+        Some(&"mate") if value > 0 => Some(MATE_SCORE - (value * 2 - 1)),
+        Some(&"mate") => Some(-MATE_SCORE - value * 2),
+        _ => None
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+// The start position, the moves and the clocks of a position to search
+type EngineRequest = (Option<String>, Vec<String>, Option<[Clock; 2]>);
+
+// The engine lives on its own thread, so the game keeps running while it thinks
+pub struct EngineController {
+    path: String,
+    requests: Mutex<Option<Sender<EngineRequest>>>,
+    replies: Mutex<Receiver<Result<(String, Option<i32>), String>>>,
+    thread: Option<JoinHandle<()>>,
+    // The ply the engine is thinking about
+    pending: Option<usize>,
+    score: Option<i32>
+}
+
+impl EngineController {
+    pub fn new(path: &str, options: &EngineOptions) -> Self {
+        let (request_sender, request_receiver) = channel::<EngineRequest>();
+        let (reply_sender, reply_receiver) = channel();
+        let engine_path = path.to_string();
+        let (movetime, threads) = (options.movetime, options.threads);
+
+        let thread = thread::spawn(move || {
+            let mut engine = match UciEngine::start(&engine_path, threads) {
+                Ok(engine) => engine,
+                Err(e) => {
+                    let _ = reply_sender.send(Err(e));
+                    return;
+                }
+            };
+
+            for request in request_receiver.iter() {
+                if reply_sender.send(engine.best_move(&request, movetime)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        EngineController {
+            path: path.to_string(),
+            requests: Mutex::new(Some(request_sender)),
+            replies: Mutex::new(reply_receiver),
+            thread: Some(thread),
+            pending: None,
+            score: None
+        }
+    }
+}
+
+impl PlayerController for EngineController {
+    fn description(&self) -> String {
+        format!("engine:{}", self.path)
+    }
+
+    fn poll(&mut self, view: &GameView) -> ControllerMove {
+        let reply = self.replies.lock().unwrap().try_recv();
+
+        match reply {
+            Ok(Err(e)) => return ControllerMove::Failed(e),
+            Ok(Ok((uci, score))) if self.pending == Some(view.history.len()) => {
+                self.pending = None;
+                self.score = score;
+
+                return match find_uci_move(view.board, view.initial_pos, view.current_move, &uci) {
+                    Some(chess_move) => ControllerMove::Ready(to_request(&chess_move)),
+                    None => ControllerMove::Failed(format!("engine played an illegal move {}", uci))
+                };
+            },
+            Ok(Ok(_)) => self.pending = None,
+            Err(_) => ()
+        }
+
+        if self.pending.is_none() {
+            if let Some(requests) = self.requests.lock().unwrap().as_ref() {
+                let start = view.start.map(|fen| fen.to_string());
+
+                if requests.send((start, view.history.clone(), view.clocks)).is_err() {
+                    return ControllerMove::Failed("engine stopped".to_string());
+                }
+            }
+
+            self.pending = Some(view.history.len());
+        }
+
+        ControllerMove::Waiting
+    }
+
+    fn score(&self) -> Option<i32> {
+        self.score
+    }
+}
+
+impl Drop for EngineController {
+    fn drop(&mut self) {
+        // Closing the request channel ends the engine thread, which then tells the engine to quit
+        self.requests.lock().unwrap().take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct ReplayController {
+    path: String,
+    moves: Vec<String>
+}
+
+impl ReplayController {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+
+        // Move numbers like "12." and a trailing result are skipped, only the moves are kept
+        let moves = text.split_whitespace()
+            .filter(|token| !token.ends_with('.') && !["1-0", "0-1", "1/2-1/2", "*"].contains(token))
+            .map(|token| token.to_string())
+            .collect();
+
+        Ok(ReplayController { path: path.to_string(), moves })
+    }
+}
+
+impl PlayerController for ReplayController {
+    fn description(&self) -> String {
+        format!("script:{}", self.path)
+    }
+
+    fn poll(&mut self, view: &GameView) -> ControllerMove {
+        let text = match self.moves.get(view.history.len()) {
+            Some(text) => text,
+            None => return ControllerMove::Exhausted
+        };
+
+        match find_move(view.board, view.initial_pos, view.current_move, text) {
+            Ok(chess_move) => ControllerMove::Ready(to_request(&chess_move)),
+            Err(e) => ControllerMove::Failed(e)
+        }
+    }
+}
+
+// Reads moves typed into the terminal, a line is only read while it's this player's turn
+#[derive(Default)]
+pub struct ConsoleController {
+    reading: Option<Mutex<Receiver<io::Result<String>>>>
+}
+
+impl PlayerController for ConsoleController {
+    fn description(&self) -> String {
+        "stdin".to_string()
+    }
+
+    fn poll(&mut self, view: &GameView) -> ControllerMove {
+        let line = match &self.reading {
+            Some(receiver) => receiver.lock().unwrap().try_recv(),
+            None => {
+                let side = if let PieceColor::White = view.current_move { "White" } else { "Black" };
+                eprint!("{} to move: ", side);
+
+                let (sender, receiver) = channel();
+
+                thread::spawn(move || {
+                    let mut line = String::new();
+                    let _ = sender.send(io::stdin().read_line(&mut line).map(|_| line));
+                });
+
+                self.reading = Some(Mutex::new(receiver));
+                return ControllerMove::Waiting;
+            }
+        };
+
+        let line = match line {
+            Ok(line) => line,
+            Err(TryRecvError::Empty) => return ControllerMove::Waiting,
+            Err(TryRecvError::Disconnected) => return ControllerMove::Failed("stdin closed".to_string())
+        };
+
+        self.reading = None;
+
+        match line {
+            Ok(line) if line.is_empty() => ControllerMove::Exhausted,
+            Ok(line) => match find_move(view.board, view.initial_pos, view.current_move, &line) {
+                Ok(chess_move) => ControllerMove::Ready(to_request(&chess_move)),
+                Err(e) => ControllerMove::Failed(e)
+            },
+            Err(e) => ControllerMove::Failed(e.to_string())
+        }
+    }
+}
+
+// How the engines think, for the built-in one and for UCI engines alike
+#[derive(Clone)]
+pub struct EngineOptions {
+    // Only for UCI engines not playing against the clock
+    pub movetime: u64,
+    pub threads: usize
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            movetime: DEFAULT_MOVETIME,
+            threads: DEFAULT_THREADS
+        }
+    }
+}
+
+// Descriptions look like mouse, keyboard, ai, ai:DEPTH, engine:PATH, script:FILE or stdin
+pub fn parse_controller(description: &str, options: &EngineOptions) -> Result<Box<dyn PlayerController>, String> {
+    if description == "mouse" {
+        Ok(Box::new(HumanController::mouse()))
+    } else if description == "keyboard" {
+        Ok(Box::new(HumanController::keyboard()))
+    } else if description == "stdin" {
+        Ok(Box::new(ConsoleController::default()))
+    } else if description == "ai" {
+        Ok(Box::new(AiController::new(None, options.threads)))
+    } else if let Some(depth) = description.strip_prefix("ai:") {
+        let depth = depth.parse().map_err(|_| format!("invalid search depth: {}", depth))?;
+        Ok(Box::new(AiController::new(Some(depth), options.threads)))
+    } else if let Some(path) = description.strip_prefix("engine:") {
+        Ok(Box::new(EngineController::new(path, options)))
+    } else if let Some(path) = description.strip_prefix("script:") {
+        Ok(Box::new(ReplayController::load(path)?))
+    } else {
+        Err(format!("unknown player: {}", description))
+    }
+}
+
+pub struct Controllers {
+    pub white: Box<dyn PlayerController>,
+    pub black: Box<dyn PlayerController>
+}
+
+impl Default for Controllers {
+    fn default() -> Self {
+        Controllers {
+            white: Box::new(HumanController::mouse()),
+            black: Box::new(HumanController::mouse())
+        }
+    }
+}
+
+impl Controllers {
+    pub fn get(&self, color: PieceColor) -> &dyn PlayerController {
+        match color {
+            PieceColor::White => self.white.as_ref(),
+            PieceColor::Black => self.black.as_ref()
+        }
+    }
+
+    pub fn get_mut(&mut self, color: PieceColor) -> &mut Box<dyn PlayerController> {
+        match color {
+            PieceColor::White => &mut self.white,
+            PieceColor::Black => &mut self.black
+        }
+    }
+
+    pub fn set(&mut self, color: PieceColor, controller: Box<dyn PlayerController>) {
+        *self.get_mut(color) = controller;
+    }
+}
+
+pub fn controller_system(mut input_reader: Local<EventReader<PlayerInput>>, inputs: Res<Events<PlayerInput>>,
+                         mut move_requests: ResMut<Events<MoveRequested>>,
+                         mut controllers: ResMut<Controllers>, shared_data: Res<SharedData>,
+                         tasks: Res<AsyncComputeTaskPool>, search_tasks: Res<SearchTaskPool>) {
+    for input in input_reader.iter(&inputs) {
+        let controller = controllers.get_mut(input.color);
+
+        if controller.accepts(input.source) {
+            controller.offer(input.request);
+        }
+    }
+
+    if let GameState::WaitingForSelect | GameState::PieceSelected = shared_data.game_state {} else {
+        return;
+    }
+
+    let color = shared_data.current_move;
+    let view = GameView { tasks: Some((&tasks, &search_tasks.0)), ..GameView::new(&shared_data) };
+    let reply = controllers.get_mut(color).poll(&view);
+    let side = if let PieceColor::White = color { "White" } else { "Black" };
+
+    // A player that can't go on is handed over to the mouse, so the game can still be finished by hand
+    let problem = match reply {
+        ControllerMove::Waiting => return,
+        ControllerMove::Ready(request) => {
+            move_requests.send(request);
+            return;
+        },
+        ControllerMove::Exhausted => "has no more moves".to_string(),
+        ControllerMove::Failed(e) => format!("can't move: {}", e)
+    };
+
+    println!("{} ({}) {}, continuing with the mouse", side, controllers.get(color).description(), problem);
+    controllers.set(color, Box::new(HumanController::mouse()));
+}
+
+// The pool the helper threads of the built-in engine search on. The main search runs as a task on the
+// AsyncComputeTaskPool and waits there for its helpers, on the same pool it would hold up a thread they need.
+pub struct SearchTaskPool(pub TaskPool);
+
+impl Default for SearchTaskPool {
+    fn default() -> Self {
+        SearchTaskPool(TaskPoolBuilder::new().thread_name("search".to_string()).build())
+    }
+}
+
+pub struct PlayerControllerPlugin;
+
+impl Plugin for PlayerControllerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(Controllers::default())
+            .add_resource(EngineOptions::default())
+            .add_resource(SearchTaskPool::default())
+            .add_event::<PlayerInput>()
+            .add_system_to_stage(stage::PRE_UPDATE, controller_system.system());
+    }
+}
@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+use bevy_mod_picking::*;
+
+use crate::board::{ChessBoard, ChessPiece, Meshes, PieceMaterials, piece_mesh};
+use crate::fen::format_fen;
+use crate::rules::{GameState, LogicChessPiece, PieceColor, PieceType, PositionEdited, SharedData,
+                   castling_rights, print_board, unmoved_pieces, validate_position};
+
+pub struct EditorPalette;
+
+#[derive(Default)]
+pub struct EditorState {
+    pub editor_piece: Option<LogicChessPiece>,
+    pub castling_rights: [bool; 4]
+}
+
+pub fn print_editor_status(shared_data: &SharedData, editor_state: &EditorState) {
+    let side = match shared_data.current_move {
+        PieceColor::White => "white",
+        PieceColor::Black => "black"
+    };
+    let rights = &editor_state.castling_rights;
+    let castling: String = ['K', 'Q', 'k', 'q'].iter().zip(rights.iter())
+        .filter(|(_, right)| **right)
+        .map(|(symbol, _)| *symbol)
+        .collect();
+
+    println!("Editor: {} to move, castling: {}", side,
+             if castling.is_empty() { "-".to_string() } else { castling });
+}
+
+pub fn editor_toggle_system(commands: &mut Commands, keyboard_input: Res<Input<KeyCode>>,
+                            piece_materials: Res<PieceMaterials>, meshes: Res<Meshes>, mut editor_state: ResMut<EditorState>,
+                            mut shared_data: ResMut<SharedData>) {
+    if !keyboard_input.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    if let GameState::WaitingForSelect = shared_data.game_state {} else {
+        return;
+    }
+
+    if shared_data.local_color.is_some() {
+        return;
+    }
+
+    let piece_types = [ PieceType::King, PieceType::Queen, PieceType::Rook,
+                        PieceType::Bishop, PieceType::Knight, PieceType::Pawn ];
+
+    for &piece_color in [PieceColor::White, PieceColor::Black].iter() {
+        let z = match piece_color {
+            PieceColor::White => 5.,
+            PieceColor::Black => -5.
+        };
+
+        for (i, &piece_type) in piece_types.iter().enumerate() {
+            commands.spawn(PbrBundle {
+                mesh: piece_mesh(&meshes, piece_type),
+                material: piece_materials.get(piece_color),
+                transform: meshes.piece_transform(piece_type, piece_color, Vec3::new(i as f32 - 2.5, 0.0, z)),
+                ..Default::default()})
+                .with(PickableMesh::default())
+                .with(InteractableMesh::default())
+                .with(piece_type)
+                .with(piece_color)
+                .with(EditorPalette);
+        }
+    }
+
+    editor_state.castling_rights = castling_rights(&shared_data.board, &shared_data.initial_pos);
+
+    editor_state.editor_piece = None;
+    shared_data.game_state = GameState::Editing;
+
+    println!("Editor: pick a piece from the palette and click a square to place it, \
+              clicking it again removes it");
+    println!("Editor: Delete - remove hovered piece, C - clear board, Tab - side to move, \
+              1-4 - toggle KQkq castling, Enter - start playing, Shift+Enter - analyse");
+    print_editor_status(&shared_data, &editor_state);
+}
+
+pub fn editor_system(commands: &mut Commands, keyboard_input: Res<Input<KeyCode>>,
+                     mut palette_query: Query<(&InteractableMesh, &mut Handle<StandardMaterial>, &PieceType, &PieceColor, Entity), With<EditorPalette>>,
+                     mut square_query: Query<(&InteractableMesh, ), Or<(With<ChessBoard>, With<ChessPiece>)>>,
+                     piece_materials: Res<PieceMaterials>, mut editor_state: ResMut<EditorState>,
+                     mut edits: ResMut<Events<PositionEdited>>, mut shared_data: ResMut<SharedData>) {
+    if let GameState::Editing = shared_data.game_state {} else {
+        return;
+    }
+
+    let mut picked = None;
+
+    for (interactable, _, piece_type, piece_color, entity) in palette_query.iter_mut() {
+        let mouse_down_event = interactable
+            .mouse_down_event(&Group::default(), MouseButton::Left)
+            .unwrap();
+
+        if let MouseDownEvents::MouseJustReleased = mouse_down_event {
+            picked = Some((entity, LogicChessPiece {
+                piece_color: *piece_color,
+                piece_type: *piece_type
+            }));
+        }
+    }
+
+    if let Some((picked_entity, piece)) = picked {
+        for (_, mut material, _, piece_color, entity) in palette_query.iter_mut() {
+            *material = if entity == picked_entity {
+                piece_materials.selected.clone()
+            } else {
+                piece_materials.get(*piece_color)
+            };
+        }
+
+        editor_state.editor_piece = Some(piece);
+    }
+
+    let mut square_clicked = false;
+
+    for (interactable, ) in square_query.iter_mut() {
+        let mouse_down_event = interactable
+            .mouse_down_event(&Group::default(), MouseButton::Left)
+            .unwrap();
+
+        if let MouseDownEvents::MouseJustReleased = mouse_down_event {
+            square_clicked = true;
+        }
+    }
+
+    let position = shared_data.cursor_board_pos;
+    let on_board = position.x < 8 && position.y < 8;
+    let remove = keyboard_input.just_pressed(KeyCode::Delete);
+
+    if on_board && (square_clicked || remove) {
+        let current = shared_data.board[position.y as usize][position.x as usize];
+        shared_data.board[position.y as usize][position.x as usize] = None;
+
+        if let (Some(piece), false) = (editor_state.editor_piece, remove) {
+            let same_piece = match current {
+                Some(current) => current.piece_color == piece.piece_color
+                    && current.piece_type == piece.piece_type,
+                None => false
+            };
+
+            if !same_piece {
+                shared_data.board[position.y as usize][position.x as usize] = Some(piece);
+            }
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::C) {
+        shared_data.board = vec![vec![None; 8]; 8];
+    }
+
+    let castling_keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+
+    for (i, key) in castling_keys.iter().enumerate() {
+        if keyboard_input.just_pressed(*key) {
+            editor_state.castling_rights[i] = !editor_state.castling_rights[i];
+            print_editor_status(&shared_data, &editor_state);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        shared_data.current_move = match shared_data.current_move {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White
+        };
+        print_editor_status(&shared_data, &editor_state);
+    }
+
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    if let Err(message) = validate_position(&shared_data.board, shared_data.current_move,
+                                            &editor_state.castling_rights) {
+        println!("Editor: illegal position: {}", message);
+        return;
+    }
+
+    shared_data.initial_pos = unmoved_pieces(&shared_data.board, &editor_state.castling_rights);
+    // The game starts over from the edited position
+    shared_data.start = Some(format_fen(&shared_data.board, &shared_data.initial_pos, shared_data.current_move, 0, 1));
+    shared_data.move_history = Vec::new();
+    shared_data.hints = [0, 0];
+
+    for (_, _, _, _, entity) in palette_query.iter_mut() {
+        commands.despawn(entity);
+    }
+
+    editor_state.editor_piece = None;
+    shared_data.game_state = GameState::WaitingForSelect;
+    edits.send(PositionEdited {
+        analyse: keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift)
+    });
+
+    print_board(&shared_data.board);
+}
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(EditorState::default())
+            .add_system(editor_toggle_system.system())
+            .add_system(editor_system.system());
+    }
+}
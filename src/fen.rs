@@ -0,0 +1,234 @@
+use crate::rules::{LogicChessPiece, PieceColor, PieceType,
+                   castling_rights, parse_position, piece_letter, unmoved_pieces, validate_position};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+pub struct FenPosition {
+    pub board: Vec<Vec<Option<LogicChessPiece>>>,
+    pub initial_pos: Vec<Vec<bool>>,
+    pub current_move: PieceColor,
+    // Plies since the last capture or pawn move, for the fifty-move rule
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32
+}
+
+impl FenPosition {
+    // Plies played before this position, so the moves after it are numbered on from there
+    pub fn plies_before(&self) -> usize {
+        (self.fullmove_number.max(1) as usize - 1) * 2 + if let PieceColor::Black = self.current_move { 1 } else { 0 }
+    }
+}
+
+fn parse_piece(c: char) -> Option<LogicChessPiece> {
+    let piece_type = match c.to_ascii_uppercase() {
+        'K' => PieceType::King,
+        'Q' => PieceType::Queen,
+        'R' => PieceType::Rook,
+        'B' => PieceType::Bishop,
+        'N' => PieceType::Knight,
+        'P' => PieceType::Pawn,
+        _ => return None
+    };
+    let piece_color = if c.is_ascii_uppercase() { PieceColor::White } else { PieceColor::Black };
+
+    Some(LogicChessPiece { piece_type, piece_color })
+}
+
+// Reads FEN as well as EPD, whose lines stop after the en passant square and may go on with operations like
+// `bm Nf3;`. The rules know no en passant, so that field is only checked.
+pub fn parse_fen(text: &str) -> Result<FenPosition, String> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+
+    if fields.len() < 4 {
+        return Err(format!("not a FEN position: {}", text));
+    }
+
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+
+    if ranks.len() != 8 {
+        return Err(format!("a FEN board needs 8 ranks: {}", fields[0]));
+    }
+
+    let mut board = vec![vec![None; 8]; 8];
+
+    // FEN starts with the eighth rank
+    for (i, rank) in ranks.iter().enumerate() {
+        let y = 7 - i;
+        let mut x = 0;
+
+        for c in rank.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                x += empty as usize;
+            } else {
+                let piece = parse_piece(c).ok_or(format!("unknown piece in FEN: {}", c))?;
+
+                if x < 8 {
+                    board[y][x] = Some(piece);
+                }
+
+                x += 1;
+            }
+        }
+
+        if x != 8 {
+            return Err(format!("rank {} of the FEN doesn't have 8 squares: {}", y + 1, rank));
+        }
+    }
+
+    let current_move = match fields[1] {
+        "w" => PieceColor::White,
+        "b" => PieceColor::Black,
+        other => return Err(format!("unknown side to move in FEN: {}", other))
+    };
+
+    let mut rights = [false; 4];
+
+    if fields[2] != "-" {
+        for c in fields[2].chars() {
+            let index = "KQkq".find(c).ok_or(format!("unknown castling right in FEN: {}", c))?;
+            rights[index] = true;
+        }
+    }
+
+    if fields[3] != "-" && parse_position(fields[3]).is_none() {
+        return Err(format!("invalid en passant square in FEN: {}", fields[3]));
+    }
+
+    validate_position(&board, current_move, &rights)?;
+
+    // EPD has no move counters, its fifth field is already an operation
+    let counter = |i: usize| fields.get(i).and_then(|field| field.parse::<u32>().ok());
+    let (halfmove_clock, fullmove_number) = match (counter(4), counter(5)) {
+        (Some(halfmove_clock), Some(fullmove_number)) => (halfmove_clock, fullmove_number),
+        _ => (0, 1)
+    };
+
+    Ok(FenPosition {
+        initial_pos: unmoved_pieces(&board, &rights),
+        board,
+        current_move,
+        halfmove_clock,
+        fullmove_number
+    })
+}
+
+pub fn format_fen(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], current_move: PieceColor,
+                  halfmove_clock: u32, fullmove_number: u32) -> String {
+    let mut ranks = Vec::new();
+
+    for row in board.iter().rev() {
+        let mut rank = String::new();
+        let mut empty = 0;
+
+        for field in row.iter() {
+            match field {
+                Some(piece) => {
+                    if empty > 0 {
+                        rank += &empty.to_string();
+                        empty = 0;
+                    }
+
+                    let letter = piece_letter(piece.piece_type);
+
+                    rank.push(match piece.piece_color {
+                        PieceColor::White => letter,
+                        PieceColor::Black => letter.to_ascii_lowercase()
+                    });
+                },
+                None => empty += 1
+            }
+        }
+
+        if empty > 0 {
+            rank += &empty.to_string();
+        }
+
+        ranks.push(rank);
+    }
+
+    let castling: String = "KQkq".chars().zip(castling_rights(board, initial_pos).iter())
+        .filter(|(_, right)| **right)
+        .map(|(c, _)| c)
+        .collect();
+
+    format!("{} {} {} - {} {}", ranks.join("/"), if let PieceColor::White = current_move { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { &castling }, halfmove_clock, fullmove_number)
+}
+
+// The position without the move counters, the same for every repetition of a position
+pub fn position_key(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                    current_move: PieceColor) -> String {
+    let fen = format_fen(board, initial_pos, current_move, 0, 1);
+
+    fen.rsplitn(3, ' ').last().unwrap_or(&fen).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(fen: &str) -> String {
+        let position = parse_fen(fen).unwrap();
+
+        format_fen(&position.board, &position.initial_pos, position.current_move, position.halfmove_clock,
+                   position.fullmove_number)
+    }
+
+    #[test]
+    fn start_position_round_trips() {
+        assert_eq!(round_trip(START_FEN), START_FEN);
+    }
+
+    #[test]
+    fn side_castling_rights_and_counters_round_trip() {
+        let fen = "r3k2r/pp3ppp/8/3pP3/8/8/PP3PPP/R3K2R b Kq - 5 20";
+
+        assert_eq!(round_trip(fen), fen);
+
+        let position = parse_fen(fen).unwrap();
+
+        assert!(matches!(position.current_move, PieceColor::Black));
+        assert_eq!((position.halfmove_clock, position.fullmove_number), (5, 20));
+        assert_eq!(position.plies_before(), 39);
+    }
+
+    #[test]
+    fn epd_has_no_counters() {
+        let position = parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 bm e5;").unwrap();
+
+        assert_eq!((position.halfmove_clock, position.fullmove_number), (0, 1));
+        assert_eq!(position.plies_before(), 1);
+    }
+
+    #[test]
+    fn broken_positions_are_rejected() {
+        assert!(parse_fen("rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_err());
+        assert!(parse_fen("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_err());
+        assert!(parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1").is_err());
+        assert!(parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkX - 0 1").is_err());
+        assert!(parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq z9 0 1").is_err());
+        // No white king, and a pawn on the last rank
+        assert!(parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1BNR w kq - 0 1").is_err());
+        assert!(parse_fen("Pnbqkbnr/pppppppp/8/8/8/8/PPPPPPP1/RNBQKBNR w KQk - 0 1").is_err());
+    }
+
+    #[test]
+    fn castling_rights_need_the_pieces_at_home() {
+        let position = parse_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+
+        assert_eq!(castling_rights(&position.board, &position.initial_pos), [true, false, false, false]);
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1").is_err());
+    }
+
+    #[test]
+    fn position_key_ignores_the_move_counters() {
+        let first = parse_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        let second = parse_fen("4k3/8/8/8/8/8/8/4K2R w K - 12 40").unwrap();
+        let key = |position: &FenPosition| position_key(&position.board, &position.initial_pos,
+                                                        position.current_move);
+
+        assert_eq!(key(&first), "4k3/8/8/8/8/8/8/4K2R w K -");
+        assert_eq!(key(&first), key(&second));
+        assert_ne!(key(&first), position_key(&first.board, &first.initial_pos, PieceColor::Black));
+    }
+}
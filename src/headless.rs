@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, TimeControl};
+use crate::controller::{ControllerMove, EngineOptions, GameView, PlayerController, parse_controller};
+use crate::fen::{FenPosition, START_FEN, parse_fen, position_key};
+use crate::rules::{GameStatus, LogicChessPiece, MoveKind, PieceColor, PieceType,
+                   chess_move_to_uci, game_status, insufficient_material, make_move, matching_moves};
+use crate::pgn::{format_pgn_from, move_to_san, result_string};
+use crate::review::{annotations, review_game, summary_text};
+
+// Scores have to hold for this many plies in a row before a game is adjudicated
+const ADJUDICATION_PLIES: usize = 8;
+
+// Draws are only adjudicated by score from this ply on, openings are often level
+const DRAW_ADJUDICATION_PLY: usize = 80;
+
+struct HeadlessOptions {
+    white: String,
+    black: String,
+    pgn_path: Option<String>,
+    // The FEN the game starts from
+    start: Option<String>,
+    max_plies: usize,
+    engine: EngineOptions,
+    // Both players start with this much time when set, and get the increment after every move
+    time: Option<Duration>,
+    increment: Duration,
+    // The time is given again after this many moves of a player, as in classical time controls
+    moves_per_control: Option<u32>,
+    // The game is reviewed at this depth and the PGN annotated
+    review_depth: Option<u32>
+}
+
+fn parse_options(args: &[String]) -> Result<HeadlessOptions, String> {
+    let mut options = HeadlessOptions {
+        white: "stdin".to_string(),
+        black: "stdin".to_string(),
+        pgn_path: None,
+        start: None,
+        max_plies: 1000,
+        engine: EngineOptions::default(),
+        time: None,
+        increment: Duration::from_secs(0),
+        moves_per_control: None,
+        review_depth: None
+    };
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("missing value for {}", arg))?;
+
+        match arg.as_str() {
+            "--white" => options.white = value.clone(),
+            "--black" => options.black = value.clone(),
+            "--pgn" => options.pgn_path = Some(value.clone()),
+            "--fen" => options.start = Some(value.clone()),
+            "--max-plies" => options.max_plies = value.parse().map_err(|_| "invalid --max-plies")?,
+            "--movetime" => options.engine.movetime = value.parse().map_err(|_| "invalid --movetime")?,
+            "--threads" => {
+                options.engine.threads = value.parse().ok().filter(|threads| *threads > 0).ok_or("invalid --threads")?
+            },
+            "--time" => options.time = Some(parse_seconds(value).ok_or("invalid --time")?),
+            "--increment" => options.increment = parse_seconds(value).ok_or("invalid --increment")?,
+            "--moves" => {
+                let moves = value.parse().ok().filter(|moves| *moves > 0).ok_or("invalid --moves")?;
+                options.moves_per_control = Some(moves)
+            },
+            "--review" => options.review_depth = Some(value.parse().map_err(|_| "invalid --review")?),
+            _ => return Err(format!("unknown option: {}", arg))
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn parse_seconds(value: &str) -> Option<Duration> {
+    value.parse::<f64>().ok().filter(|seconds| *seconds >= 0.).map(Duration::from_secs_f64)
+}
+
+// How a game may end before a mate. Without any of it, games only end by mate, stalemate or the move limit.
+#[derive(Copy, Clone, Default)]
+pub struct Adjudication {
+    // The fifty-move rule, threefold repetition and insufficient material
+    pub draw_rules: bool,
+    // A side wins once the engines' scores have it at least this many centipawns ahead for ADJUDICATION_PLIES
+    pub win_score: Option<i32>,
+    // A game is drawn once the scores stay within this many centipawns for ADJUDICATION_PLIES
+    pub draw_score: Option<i32>
+}
+
+pub struct GameSettings {
+    pub max_plies: usize,
+    pub time_control: Option<TimeControl>,
+    pub adjudication: Adjudication,
+    // Every move is printed to stderr as it's played
+    pub echo: bool
+}
+
+pub struct HeadlessGame {
+    pub board: Vec<Vec<Option<LogicChessPiece>>>,
+    pub initial_pos: Vec<Vec<bool>>,
+    pub current_move: PieceColor,
+    pub history: Vec<String>,
+    pub san_history: Vec<String>,
+    pub clocks: Option<[Clock; 2]>,
+    // The FEN the game started from, None for the starting position
+    pub start: Option<String>,
+    plies_before: usize,
+    halfmove_clock: u32,
+    // How often each position has been on the board, for threefold repetition
+    positions: HashMap<String, u32>,
+    // The mover's score of every ply from White's side, None when the player gave none
+    scores: Vec<Option<i32>>
+}
+
+impl HeadlessGame {
+    pub fn new(start: Option<(&str, FenPosition)>, time_control: Option<TimeControl>) -> Self {
+        let (fen, position) = match start {
+            Some((fen, position)) => (Some(fen.to_string()), position),
+            None => (None, parse_fen(START_FEN).unwrap())
+        };
+        let mut positions = HashMap::new();
+
+        positions.insert(position_key(&position.board, &position.initial_pos, position.current_move), 1);
+
+        HeadlessGame {
+            plies_before: position.plies_before(),
+            halfmove_clock: position.halfmove_clock,
+            board: position.board,
+            initial_pos: position.initial_pos,
+            current_move: position.current_move,
+            history: Vec::new(),
+            san_history: Vec::new(),
+            clocks: time_control.map(|control| [control.clock(), control.clock()]),
+            start: fen,
+            positions,
+            scores: Vec::new()
+        }
+    }
+
+    // Plays the game on until it ends and returns the result and the reason
+    pub fn play<'a>(&mut self, white: &'a mut dyn PlayerController, black: &'a mut dyn PlayerController,
+                settings: &GameSettings) -> Result<(&'static str, String), String> {
+        loop {
+            let status = game_status(&self.board, &self.initial_pos, self.current_move);
+
+            match status {
+                GameStatus::Checkmate => return Ok((result_string(status, self.current_move), "checkmate".to_string())),
+                GameStatus::Stalemate => return Ok((result_string(status, self.current_move), "stalemate".to_string())),
+                GameStatus::Ongoing => ()
+            }
+
+            if let Some(ending) = self.adjudicate(&settings.adjudication) {
+                return Ok(ending);
+            }
+
+            if self.history.len() >= settings.max_plies {
+                return Ok(("*", "move limit reached".to_string()));
+            }
+
+            let controller = match self.current_move {
+                PieceColor::White => &mut *white,
+                PieceColor::Black => &mut *black
+            };
+
+            let start = Instant::now();
+            let request = loop {
+                let view = GameView {
+                    board: &self.board,
+                    initial_pos: &self.initial_pos,
+                    current_move: self.current_move,
+                    history: &self.history,
+                    start: self.start.as_deref(),
+                    clocks: self.clocks,
+                    tasks: None
+                };
+
+                if let Some(clocks) = self.clocks {
+                    if start.elapsed() > clocks[self.current_move as usize].remaining {
+                        let (result, loser) = match self.current_move {
+                            PieceColor::White => ("0-1", "White"),
+                            PieceColor::Black => ("1-0", "Black")
+                        };
+
+                        return Ok((result, format!("{} lost on time", loser)));
+                    }
+                }
+
+                match controller.poll(&view) {
+                    ControllerMove::Waiting => thread::sleep(Duration::from_millis(10)),
+                    ControllerMove::Ready(request) => break request,
+                    ControllerMove::Exhausted => return Ok(("*", "no more moves".to_string())),
+                    ControllerMove::Failed(e) => return Err(e)
+                }
+            };
+
+            let chess_move = matching_moves(&self.board, &self.initial_pos, self.current_move, request.from,
+                                            request.to)
+                .into_iter()
+                .find(|chess_move| chess_move.promotion == request.promotion)
+                .ok_or(format!("{} played an illegal move", controller.description()))?;
+            let san = move_to_san(&self.board, &self.initial_pos, &chess_move);
+            let pawn_move = self.board[chess_move.from.y as usize][chess_move.from.x as usize]
+                .is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+
+            if settings.echo {
+                eprintln!("{}", san);
+            }
+
+            self.halfmove_clock = match chess_move.kind {
+                MoveKind::Capture => 0,
+                _ if pawn_move => 0,
+                _ => self.halfmove_clock + 1
+            };
+            self.scores.push(controller.score().map(|score| match self.current_move {
+                PieceColor::White => score,
+                PieceColor::Black => -score
+            }));
+            self.history.push(chess_move_to_uci(&chess_move));
+            self.san_history.push(san);
+            make_move(&mut self.board, &mut self.initial_pos, &chess_move);
+
+            if let (Some(clocks), Some(control)) = (self.clocks.as_mut(), settings.time_control) {
+                // The moves this player has made, the first mover's come first
+                let moves_made = (self.history.len() as u32).div_ceil(2);
+
+                control.after_move(&mut clocks[self.current_move as usize], moves_made, start.elapsed());
+            }
+
+            self.current_move = match self.current_move {
+                PieceColor::White => PieceColor::Black,
+                PieceColor::Black => PieceColor::White
+            };
+
+            *self.positions.entry(position_key(&self.board, &self.initial_pos, self.current_move)).or_insert(0) += 1;
+        }
+    }
+
+    fn adjudicate(&self, adjudication: &Adjudication) -> Option<(&'static str, String)> {
+        if adjudication.draw_rules {
+            let key = position_key(&self.board, &self.initial_pos, self.current_move);
+
+            if self.halfmove_clock >= 100 {
+                return Some(("1/2-1/2", "fifty-move rule".to_string()));
+            }
+
+            if self.positions.get(&key).is_some_and(|count| *count >= 3) {
+                return Some(("1/2-1/2", "threefold repetition".to_string()));
+            }
+
+            if insufficient_material(&self.board) {
+                return Some(("1/2-1/2", "insufficient material".to_string()));
+            }
+        }
+
+        if self.scores.len() < ADJUDICATION_PLIES {
+            return None;
+        }
+
+        let recent = &self.scores[self.scores.len() - ADJUDICATION_PLIES..];
+        let all = |holds: &dyn Fn(i32) -> bool| recent.iter().all(|score| score.is_some_and(holds));
+
+        if let Some(threshold) = adjudication.win_score {
+            if all(&|score| score >= threshold) {
+                return Some(("1-0", "adjudicated, White is winning".to_string()));
+            }
+
+            if all(&|score| score <= -threshold) {
+                return Some(("0-1", "adjudicated, Black is winning".to_string()));
+            }
+        }
+
+        if let Some(threshold) = adjudication.draw_score {
+            let ply = self.plies_before + self.history.len();
+
+            if ply >= DRAW_ADJUDICATION_PLY && all(&|score| score.abs() <= threshold) {
+                return Some(("1/2-1/2", "adjudicated draw".to_string()));
+            }
+        }
+
+        None
+    }
+
+    // The PGN of the game, `tags` are followed by the ones for the start position
+    pub fn pgn(&self, tags: &[(&str, String)], annotations: &[String], result: &str) -> String {
+        let mut tags = tags.to_vec();
+
+        if let Some(fen) = &self.start {
+            tags.push(("SetUp", "1".to_string()));
+            tags.push(("FEN", fen.clone()));
+        }
+
+        format_pgn_from(&tags, &self.san_history, annotations, result, self.plies_before)
+    }
+}
+
+pub fn headless_controller(description: &str, engine: &EngineOptions) -> Result<Box<dyn PlayerController>, String> {
+    if description == "mouse" || description == "keyboard" {
+        return Err(format!("{} players need a window", description));
+    }
+
+    parse_controller(description, engine)
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: rusty_chess --headless [--white SOURCE] [--black SOURCE] [--fen FEN] [--pgn FILE] \
+                       [--max-plies N] [--movetime MS] [--threads N] \
+                       [--time SECONDS [--increment SECONDS] [--moves N]] [--review DEPTH]");
+            eprintln!("SOURCE is one of: stdin, script:FILE, engine:PATH, ai, ai:DEPTH");
+            return 2;
+        }
+    };
+
+    let start = match &options.start {
+        Some(fen) => match parse_fen(fen) {
+            Ok(position) => Some((fen.as_str(), position)),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 2;
+            }
+        },
+        None => None
+    };
+    let settings = GameSettings {
+        max_plies: options.max_plies,
+        time_control: options.time.map(|time| TimeControl {
+            time,
+            increment: options.increment,
+            moves_per_control: options.moves_per_control
+        }),
+        // The draw rules end dead games, scores only decide engine matches
+        adjudication: Adjudication { draw_rules: true, ..Default::default() },
+        echo: true
+    };
+    let mut game = HeadlessGame::new(start, settings.time_control);
+
+    let played = headless_controller(&options.white, &options.engine).and_then(|mut white| {
+        let mut black = headless_controller(&options.black, &options.engine)?;
+        game.play(white.as_mut(), black.as_mut(), &settings)
+    });
+    let (result, reason, exit_code) = match played {
+        Ok((result, reason)) => (result, reason, 0),
+        Err(e) => ("*", e, 1)
+    };
+
+    // Only the PGN goes to stdout, so it can be piped on
+    eprintln!("Result: {} ({})", result, reason);
+
+    let mut tags = vec![
+        ("Event", "rusty_chess headless game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", "-".to_string()),
+        ("White", options.white.clone()),
+        ("Black", options.black.clone()),
+        ("Termination", reason)
+    ];
+
+    if let Some(control) = settings.time_control {
+        tags.push(("TimeControl", control.pgn_tag()));
+    }
+    let pgn = match options.review_depth {
+        Some(depth) => match review_game(game.start.as_deref(), &game.history, depth) {
+            Ok(reviews) => {
+                eprintln!("{}", summary_text(&reviews, PieceColor::White));
+                eprintln!("{}", summary_text(&reviews, PieceColor::Black));
+                game.pgn(&tags, &annotations(&reviews), result)
+            },
+            Err(e) => {
+                eprintln!("Can't review the game: {}", e);
+                game.pgn(&tags, &[], result)
+            }
+        },
+        None => game.pgn(&tags, &[], result)
+    };
+
+    match &options.pgn_path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, pgn) {
+                eprintln!("Can't write {}: {}", path, e);
+                return 1;
+            }
+        },
+        None => print!("{}", pgn)
+    }
+
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{AiController, ReplayController};
+
+    fn settings(draw_rules: bool) -> GameSettings {
+        GameSettings {
+            max_plies: 40,
+            time_control: None,
+            adjudication: Adjudication { draw_rules, ..Default::default() },
+            echo: false
+        }
+    }
+
+    // A script player reading `moves` from a file of its own
+    fn script(name: &str, moves: &str) -> ReplayController {
+        let path = std::env::temp_dir().join(format!("rusty_chess_{}_{}.txt", name, std::process::id()));
+
+        fs::write(&path, moves).unwrap();
+        let script = ReplayController::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        script
+    }
+
+    fn game_from(fen: &str) -> HeadlessGame {
+        HeadlessGame::new(Some((fen, parse_fen(fen).unwrap())), None)
+    }
+
+    #[test]
+    fn script_against_ai() {
+        let mut game = game_from("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let mut white = script("ra8", "1. Ra8#");
+        let mut black = AiController::new(Some(2), 1);
+
+        let (result, reason) = game.play(&mut white, &mut black, &settings(false)).unwrap();
+
+        assert_eq!((result, reason.as_str()), ("1-0", "checkmate"));
+        assert_eq!(game.history, ["a1a8"]);
+        assert!(game.pgn(&[], &[], result).contains("[FEN \"6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\"]"));
+    }
+
+    #[test]
+    fn ai_against_script() {
+        let mut game = game_from("6k1/5ppp/8/8/8/8/8/R5K1 b - - 0 1");
+        let mut white = AiController::new(Some(3), 1);
+        let mut black = script("kh8", "1... Kh8");
+
+        // The script ends after its move, the mate has to come right after
+        let (result, reason) = game.play(&mut white, &mut black, &settings(false)).unwrap();
+
+        assert_eq!((result, reason.as_str()), ("1-0", "checkmate"));
+        assert_eq!(game.san_history, ["Kh8", "Ra8#"]);
+        assert!(game.pgn(&[], &[], result).contains("1... Kh8 2. Ra8# 1-0"));
+    }
+
+    #[test]
+    fn draw_rules() {
+        let moves = "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. Nf3";
+        let mut game = HeadlessGame::new(None, None);
+        let (mut white, mut black) = (script("repetition_white", moves), script("repetition_black", moves));
+
+        let (result, reason) = game.play(&mut white, &mut black, &settings(true)).unwrap();
+
+        assert_eq!((result, reason.as_str()), ("1/2-1/2", "threefold repetition"));
+        assert_eq!(game.history.len(), 8);
+
+        // Without the rules the game goes on until the script ends
+        let mut game = HeadlessGame::new(None, None);
+        let (mut white, mut black) = (script("moves_white", moves), script("moves_black", moves));
+
+        assert_eq!(game.play(&mut white, &mut black, &settings(false)).unwrap().1, "no more moves");
+        assert_eq!(game.history.len(), 9);
+
+        let mut game = game_from("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1");
+        let (mut white, mut black) = (script("capture_white", "1. Kxd2"), script("capture_black", "1. Kxd2"));
+
+        assert_eq!(game.play(&mut white, &mut black, &settings(true)).unwrap().1, "insufficient material");
+    }
+}
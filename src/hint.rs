@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::pipeline::PrimitiveTopology;
+use bevy::tasks::AsyncComputeTaskPool;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+use crate::board::board_to_global;
+use crate::controller::{Controllers, InputSource};
+use crate::pgn::move_to_san;
+use crate::rules::{BoardPosition, ChessMove, GameState, LogicChessPiece, SharedData, move_destination};
+use crate::search;
+
+#[derive(Clone)]
+pub struct HintConfig {
+    pub depth: u32,
+    pub color: Color
+}
+
+impl Default for HintConfig {
+    fn default() -> Self {
+        HintConfig {
+            depth: 4,
+            color: Color::rgba(0.2, 0.8, 0.3, 0.8)
+        }
+    }
+}
+
+pub enum HintMarker {
+    From,
+    To,
+    Arrow
+}
+
+// A hint only counts for the board it was asked on, any change to the board takes it away
+#[derive(Default)]
+pub struct Hint {
+    searching: Option<(Vec<Vec<Option<LogicChessPiece>>>, Mutex<Receiver<Option<ChessMove>>>)>,
+    pub shown: Option<(Vec<Vec<Option<LogicChessPiece>>>, BoardPosition, BoardPosition)>,
+    arrow_length: f32
+}
+
+// An arrow lying on the board, pointing along x from the origin to `length`
+fn arrow_mesh(length: f32) -> Mesh {
+    let (shaft_width, head_width, head_length) = (0.06, 0.2, 0.35);
+    let neck = (length - head_length).max(0.);
+
+    let positions = vec![
+        [0.0, 0.0, shaft_width], [neck, 0.0, shaft_width], [neck, 0.0, -shaft_width], [0.0, 0.0, -shaft_width],
+        [neck, 0.0, head_width], [length, 0.0, 0.0], [neck, 0.0, -head_width]
+    ];
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    let uvs = vec![[0.0, 0.0]; positions.len()];
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(vec![0, 1, 2, 0, 2, 3, 4, 5, 6])));
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh
+}
+
+pub fn setup(commands: &mut Commands, mut meshes: ResMut<Assets<Mesh>>,
+             mut materials: ResMut<Assets<StandardMaterial>>, config: Res<HintConfig>) {
+    let material = materials.add(StandardMaterial {
+        albedo: config.color,
+        albedo_texture: None,
+        shaded: false
+    });
+    let square = meshes.add(Mesh::from(shape::Plane { size: 1.0 }));
+    let markers = vec![
+        (HintMarker::From, square.clone()),
+        (HintMarker::To, square),
+        (HintMarker::Arrow, meshes.add(arrow_mesh(1.0)))
+    ];
+
+    // Above the other overlays, so the hint is never covered by them
+    for (marker, mesh) in markers {
+        commands.spawn(PbrBundle {
+            mesh,
+            material: material.clone(),
+            transform: Transform::from_translation(Vec3::new(0.0, 0.02, 0.0)),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true
+            },
+            ..Default::default()
+        })
+            .with(marker);
+    }
+}
+
+// H asks the engine for the best move of the side to move, only for a player sitting at this screen.
+// While a move is typed H is part of it, Ctrl+H gets through the entry instead.
+pub fn hint_request_system(keyboard_input: Res<Input<KeyCode>>, config: Res<HintConfig>,
+                           controllers: Res<Controllers>, tasks: Res<AsyncComputeTaskPool>, mut hint: ResMut<Hint>,
+                           mut shared_data: ResMut<SharedData>) {
+    if !keyboard_input.just_pressed(KeyCode::H) || hint.searching.is_some() {
+        return;
+    }
+
+    if let GameState::WaitingForSelect | GameState::PieceSelected = shared_data.game_state {} else {
+        return;
+    }
+
+    let color = shared_data.current_move;
+
+    if !controllers.get(color).accepts(InputSource::Keyboard) {
+        return;
+    }
+
+    let (sender, receiver) = channel();
+    let (board, initial_pos, depth) = (shared_data.board.clone(), shared_data.initial_pos.clone(), config.depth);
+
+    tasks.spawn(async move {
+        let best = search::search(&board, &initial_pos, color, depth).map(|(chess_move, _)| chess_move);
+        let _ = sender.send(best);
+    }).detach();
+
+    hint.searching = Some((shared_data.board.clone(), Mutex::new(receiver)));
+    shared_data.hints[color as usize] += 1;
+
+    println!("Hint: thinking...");
+}
+
+pub fn hint_system(shared_data: Res<SharedData>, mut hint: ResMut<Hint>, mut meshes: ResMut<Assets<Mesh>>,
+                   mut query: Query<(&HintMarker, &mut Transform, &mut Visible, &mut Handle<Mesh>)>) {
+    let result = match &hint.searching {
+        Some((board, receiver)) => receiver.lock().unwrap().try_recv().ok().map(|best| (board.clone(), best)),
+        None => None
+    };
+
+    if let Some((board, best)) = result {
+        hint.searching = None;
+
+        match best {
+            Some(chess_move) if board == shared_data.board => {
+                println!("Hint: {}", move_to_san(&shared_data.board, &shared_data.initial_pos, &chess_move));
+                hint.shown = Some((board, chess_move.from, move_destination(&chess_move)));
+            },
+            Some(_) => (),
+            None => println!("Hint: no legal moves")
+        }
+    }
+
+    let editing = matches!(shared_data.game_state, GameState::Editing);
+
+    if hint.shown.as_ref().is_some_and(|(board, _, _)| editing || *board != shared_data.board) {
+        hint.shown = None;
+    }
+
+    let squares = hint.shown.as_ref().map(|(_, from, to)| (*from, *to));
+
+    for (marker, mut transform, mut visible, mut mesh) in query.iter_mut() {
+        visible.is_visible = squares.is_some();
+
+        let (from, to) = match squares {
+            Some(squares) => squares,
+            None => continue
+        };
+        let (start, end) = (board_to_global(from), board_to_global(to));
+        let height = transform.translation.y;
+
+        match marker {
+            HintMarker::From => transform.translation = start,
+            HintMarker::To => transform.translation = end,
+            HintMarker::Arrow => {
+                let offset = end - start;
+                let length = offset.length();
+
+                // Only rebuilt when the arrow's length changes
+                if (hint.arrow_length - length).abs() > 0.001 {
+                    *mesh = meshes.add(arrow_mesh(length));
+                    hint.arrow_length = length;
+                }
+
+                transform.translation = start;
+                transform.rotation = Quat::from_rotation_y((-offset.z).atan2(offset.x));
+            }
+        }
+
+        transform.translation.y = height;
+    }
+}
+
+#[derive(Default)]
+pub struct HintPlugin {
+    pub config: HintConfig
+}
+
+impl Plugin for HintPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(Hint::default())
+            .add_startup_system(setup.system())
+            .add_system(hint_request_system.system())
+            .add_system(hint_system.system());
+    }
+}
@@ -0,0 +1,16 @@
+use bevy::prelude::*;
+
+pub const FONT: &str = "fonts/DejaVuSansMono.ttf";
+
+pub fn setup(commands: &mut Commands) {
+    commands.spawn(CameraUiBundle::default());
+}
+
+// The 2D overlay camera shared by everything drawn as text on top of the board
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(setup.system());
+    }
+}
@@ -0,0 +1,369 @@
+use bevy::prelude::*;
+
+use crate::controller::{Controllers, InputSource, PlayerInput};
+use crate::hud;
+use crate::pgn::{move_to_san, strip_annotations};
+use crate::rules::{ChessMove, GameState, LogicChessPiece, MoveRequested, PieceColor, SharedData,
+                   chess_move_to_uci, legal_moves};
+
+// How many completions are listed after the typed text
+const SHOWN_COMPLETIONS: usize = 8;
+
+#[derive(Default)]
+pub struct KeyboardEntry {
+    pub open: bool,
+    pub text: String,
+    // Why the last Enter didn't play a move, cleared by the next key
+    message: Option<String>
+}
+
+pub struct KeyboardEntryText;
+
+pub enum EntryStatus {
+    Empty,
+    Complete(ChessMove),
+    // The text is the beginning of these moves
+    Partial(Vec<(String, ChessMove)>),
+    // The text names a move that more than one piece can make
+    Ambiguous(Vec<String>),
+    Invalid
+}
+
+// Moves can be typed in SAN (Nf3, exd5, e8=Q, O-O) or as coordinates (g1f3, e7e8q)
+pub fn entry_status(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                    color: PieceColor, text: &str) -> EntryStatus {
+    let typed = strip_annotations(text.trim());
+
+    // Piece letters and castling can be typed in lower case too, except b which is also a file
+    let typed = match typed.chars().next() {
+        Some(c) if "nrqko".contains(c) => c.to_ascii_uppercase().to_string() + &typed[1..].replace('o', "O"),
+        _ => typed
+    };
+
+    if typed.is_empty() {
+        return EntryStatus::Empty;
+    }
+
+    let moves: Vec<(String, String, ChessMove)> = legal_moves(board, initial_pos, color).into_iter()
+        .map(|chess_move| (strip_annotations(&move_to_san(board, initial_pos, &chess_move)),
+                           chess_move_to_uci(&chess_move), chess_move))
+        .collect();
+
+    if let Some((_, _, chess_move)) = moves.iter().find(|(san, uci, _)| *san == typed || *uci == typed) {
+        return EntryStatus::Complete(*chess_move);
+    }
+
+    // Nd2 when both knights can go there is written Nbd2 or Nfd2, so it's no prefix of either
+    let ambiguous: Vec<String> = moves.iter()
+        .filter(|(san, _, _)| typed.len() > 1 && san.len() == typed.len() + 1
+            && san.starts_with(&typed[..1]) && san.ends_with(&typed[1..]))
+        .map(|(san, _, _)| san.clone())
+        .collect();
+
+    if ambiguous.len() > 1 {
+        return EntryStatus::Ambiguous(ambiguous);
+    }
+
+    let partial: Vec<(String, ChessMove)> = moves.into_iter()
+        .filter_map(|(san, uci, chess_move)| {
+            if san.starts_with(&typed) {
+                Some((san, chess_move))
+            } else if uci.starts_with(&typed) {
+                Some((uci, chess_move))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if partial.is_empty() {
+        EntryStatus::Invalid
+    } else {
+        EntryStatus::Partial(partial)
+    }
+}
+
+fn common_prefix(texts: &[String]) -> String {
+    let first = match texts.first() {
+        Some(first) => first,
+        None => return String::new()
+    };
+    let length = first.chars().enumerate()
+        .take_while(|(i, c)| texts.iter().all(|text| text.chars().nth(*i) == Some(*c)))
+        .count();
+
+    first.chars().take(length).collect()
+}
+
+fn send_move(inputs: &mut Events<PlayerInput>, color: PieceColor, chess_move: &ChessMove) {
+    inputs.send(PlayerInput {
+        source: InputSource::Keyboard,
+        color,
+        request: MoveRequested {
+            from: chess_move.from,
+            to: chess_move.to,
+            promotion: chess_move.promotion
+        }
+    });
+}
+
+// The keys that type a move or work the entry, everything else still reaches the other systems
+fn entry_key(key: KeyCode) -> bool {
+    use KeyCode::*;
+
+    matches!(key,
+        A | B | C | D | E | F | G | H | I | J | K | L | M | N | O | P | Q | R | S | T | U | V | W | X | Y | Z
+        | Key0 | Key1 | Key2 | Key3 | Key4 | Key5 | Key6 | Key7 | Key8 | Key9
+        | Numpad0 | Numpad1 | Numpad2 | Numpad3 | Numpad4 | Numpad5 | Numpad6 | Numpad7 | Numpad8 | Numpad9
+        | Minus | Equals | Plus | NumpadAdd | NumpadSubtract | NumpadEquals
+        | Return | NumpadEnter | Back | Tab | Escape)
+}
+
+// Runs before the other systems and swallows the keys of the move while the entry is open, so typing a move
+// doesn't trigger shortcuts like E for the editor. With Ctrl or Alt held the keys are left to the shortcuts.
+pub fn keyboard_entry_system(mut char_reader: Local<EventReader<ReceivedCharacter>>,
+                             chars: Res<Events<ReceivedCharacter>>, mut keyboard_input: ResMut<Input<KeyCode>>,
+                             mut entry: ResMut<KeyboardEntry>, mut inputs: ResMut<Events<PlayerInput>>,
+                             controllers: Res<Controllers>, shared_data: Res<SharedData>) {
+    let typed: Vec<char> = char_reader.iter(&chars).map(|received| received.char).collect();
+    let color = shared_data.current_move;
+    let controller = controllers.get(color);
+    let playing = matches!(shared_data.game_state, GameState::WaitingForSelect | GameState::PieceSelected);
+
+    if !playing || !controller.accepts(InputSource::Keyboard) {
+        if entry.open {
+            *entry = KeyboardEntry::default();
+        }
+
+        return;
+    }
+
+    // Keyboard players always type, mouse players open the entry with Enter
+    let keyboard_only = !controller.accepts(InputSource::Mouse);
+
+    if !entry.open {
+        if keyboard_only || keyboard_input.just_pressed(KeyCode::Return) {
+            entry.open = true;
+            keyboard_input.reset(KeyCode::Return);
+        }
+
+        return;
+    }
+
+    let shortcut = [KeyCode::LControl, KeyCode::RControl, KeyCode::LAlt, KeyCode::RAlt].iter()
+        .any(|key| keyboard_input.pressed(*key));
+
+    if shortcut {
+        return;
+    }
+
+    let pressed: Vec<KeyCode> = keyboard_input.get_just_pressed().cloned().filter(|key| entry_key(*key)).collect();
+
+    for key in pressed.iter() {
+        keyboard_input.reset(*key);
+    }
+
+    let typed: Vec<char> = typed.into_iter()
+        .filter(|c| c.is_ascii_alphanumeric() || "-=+#".contains(*c))
+        .collect();
+
+    if !typed.is_empty() || !pressed.is_empty() {
+        entry.message = None;
+    }
+
+    entry.text.extend(typed);
+
+    if pressed.contains(&KeyCode::Escape) {
+        *entry = KeyboardEntry { open: keyboard_only, ..Default::default() };
+        return;
+    }
+
+    if pressed.contains(&KeyCode::Back) {
+        entry.text.pop();
+    }
+
+    let submit = pressed.contains(&KeyCode::Return) || pressed.contains(&KeyCode::NumpadEnter);
+
+    if !submit && !pressed.contains(&KeyCode::Tab) {
+        return;
+    }
+
+    let status = entry_status(&shared_data.board, &shared_data.initial_pos, color, &entry.text);
+
+    match status {
+        EntryStatus::Complete(chess_move) if submit => {
+            send_move(&mut inputs, color, &chess_move);
+            *entry = KeyboardEntry { open: keyboard_only, ..Default::default() };
+        },
+        EntryStatus::Partial(moves) if moves.len() == 1 && submit => {
+            send_move(&mut inputs, color, &moves[0].1);
+            *entry = KeyboardEntry { open: keyboard_only, ..Default::default() };
+        },
+        EntryStatus::Partial(moves) if !submit => {
+            let texts: Vec<String> = moves.into_iter().map(|(text, _)| text).collect();
+            entry.text = common_prefix(&texts);
+        },
+        EntryStatus::Partial(moves) => {
+            let texts: Vec<String> = moves.into_iter().map(|(text, _)| text).collect();
+            entry.message = Some(format!("{} could be {}", entry.text, texts.join(", ")));
+        },
+        EntryStatus::Ambiguous(moves) if submit => {
+            entry.message = Some(format!("{} is ambiguous: {}", entry.text, moves.join(", ")));
+        },
+        EntryStatus::Invalid if submit => {
+            entry.message = Some(format!("{} is not a legal move", entry.text));
+        },
+        _ => ()
+    }
+}
+
+pub fn setup(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(10.),
+                bottom: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text {
+            value: String::new(),
+            font: asset_server.load(hud::FONT),
+            style: TextStyle {
+                font_size: 24.,
+                color: Color::WHITE,
+                ..Default::default()
+            }
+        },
+        ..Default::default()
+    })
+        .with(KeyboardEntryText);
+}
+
+// Shows the typed text with live validation: green for a legal move, white while it's still
+// the beginning of some moves (listed after it) and red when nothing matches
+pub fn keyboard_entry_text_system(entry: Res<KeyboardEntry>, shared_data: Res<SharedData>,
+                                  mut query: Query<&mut Text, With<KeyboardEntryText>>,
+                                  mut shown: Local<String>) {
+    // Working out the completions is slow, so it's only done when the entry or the position changes
+    let key = format!("{} {} {:?} {}", entry.open, entry.text, entry.message, shared_data.move_history.len());
+
+    if *shown == key {
+        return;
+    }
+
+    *shown = key;
+
+    let side = if let PieceColor::White = shared_data.current_move { "White" } else { "Black" };
+    let (value, color) = if !entry.open {
+        (String::new(), Color::WHITE)
+    } else if let Some(message) = &entry.message {
+        (format!("{}> {}  {}", side, entry.text, message), Color::rgb(1.0, 0.4, 0.4))
+    } else {
+        let status = entry_status(&shared_data.board, &shared_data.initial_pos,
+                                  shared_data.current_move, &entry.text);
+
+        match status {
+            EntryStatus::Empty => (format!("{}> _", side), Color::WHITE),
+            EntryStatus::Complete(_) => (format!("{}> {}", side, entry.text), Color::rgb(0.4, 1.0, 0.4)),
+            EntryStatus::Partial(moves) => {
+                let mut texts: Vec<String> = moves.into_iter().map(|(text, _)| text).collect();
+
+                if texts.len() > SHOWN_COMPLETIONS {
+                    texts.truncate(SHOWN_COMPLETIONS);
+                    texts.push("...".to_string());
+                }
+
+                (format!("{}> {}  {}", side, entry.text, texts.join(" ")), Color::WHITE)
+            },
+            EntryStatus::Ambiguous(moves) => {
+                (format!("{}> {}  ambiguous: {}", side, entry.text, moves.join(" ")), Color::rgb(1.0, 0.8, 0.3))
+            },
+            EntryStatus::Invalid => (format!("{}> {}  no legal move", side, entry.text), Color::rgb(1.0, 0.4, 0.4))
+        }
+    };
+
+    for mut text in query.iter_mut() {
+        text.value = value.clone();
+        text.style.color = color;
+    }
+}
+
+pub struct KeyboardEntryPlugin;
+
+impl Plugin for KeyboardEntryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(KeyboardEntry::default())
+            .add_startup_system(setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, keyboard_entry_system.system())
+            .add_system(keyboard_entry_text_system.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::{START_FEN, parse_fen};
+
+    fn status(fen: &str, text: &str) -> EntryStatus {
+        let position = parse_fen(fen).unwrap();
+
+        entry_status(&position.board, &position.initial_pos, position.current_move, text)
+    }
+
+    fn completed(fen: &str, text: &str) -> Option<String> {
+        match status(fen, text) {
+            EntryStatus::Complete(chess_move) => Some(chess_move_to_uci(&chess_move)),
+            _ => None
+        }
+    }
+
+    const KNIGHTS: &str = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+
+    #[test]
+    fn both_knights_to_d2() {
+        match status(KNIGHTS, "Nd2") {
+            EntryStatus::Ambiguous(mut moves) => {
+                moves.sort();
+                assert_eq!(moves, ["Nbd2", "Nfd2"]);
+            },
+            _ => panic!("Nd2 should be ambiguous")
+        }
+
+        assert_eq!(completed(KNIGHTS, "Nbd2"), Some("b1d2".to_string()));
+        assert_eq!(completed(KNIGHTS, "Nfd2"), Some("f3d2".to_string()));
+        assert_eq!(completed(KNIGHTS, "nfd2"), Some("f3d2".to_string()));
+    }
+
+    #[test]
+    fn san_and_coordinates() {
+        assert_eq!(completed(START_FEN, "Nf3"), Some("g1f3".to_string()));
+        assert_eq!(completed(START_FEN, "nf3"), Some("g1f3".to_string()));
+        assert_eq!(completed(START_FEN, "e2e4"), Some("e2e4".to_string()));
+        assert_eq!(completed(START_FEN, "e4!"), Some("e2e4".to_string()));
+        assert_eq!(completed("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "o-o"), Some("e1g1".to_string()));
+    }
+
+    #[test]
+    fn unfinished_and_wrong_text() {
+        assert!(matches!(status(START_FEN, ""), EntryStatus::Empty));
+        assert!(matches!(status(START_FEN, "Nf4"), EntryStatus::Invalid));
+        assert!(matches!(status(START_FEN, "e2e5"), EntryStatus::Invalid));
+
+        match status(START_FEN, "N") {
+            EntryStatus::Partial(moves) => {
+                let mut moves: Vec<String> = moves.into_iter().map(|(san, _)| san).collect();
+                moves.sort();
+                assert_eq!(moves, ["Na3", "Nc3", "Nf3", "Nh3"]);
+            },
+            _ => panic!("N should start four moves")
+        }
+
+        match status(START_FEN, "g1") {
+            EntryStatus::Partial(moves) => assert_eq!(moves.len(), 2),
+            _ => panic!("g1 should start two moves")
+        }
+    }
+}
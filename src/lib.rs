@@ -0,0 +1,73 @@
+// Bevy systems take every resource and query they use as an argument
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+use bevy::app::PluginGroupBuilder;
+use bevy::prelude::*;
+
+pub mod analysis;
+pub mod board;
+pub mod camera;
+pub mod clock;
+pub mod controller;
+pub mod editor;
+pub mod fen;
+pub mod headless;
+pub mod hint;
+pub mod hud;
+pub mod keyboard;
+pub mod network;
+pub mod overlay;
+pub mod pgn;
+pub mod picking;
+pub mod promotion;
+pub mod puzzle;
+pub mod review;
+pub mod rules;
+pub mod save;
+pub mod search;
+pub mod theme;
+pub mod tournament;
+pub mod tray;
+pub mod uci;
+
+pub use analysis::AnalysisPlugin;
+pub use board::ChessBoardRenderPlugin;
+pub use camera::OrbitCameraPlugin;
+pub use controller::PlayerControllerPlugin;
+pub use editor::EditorPlugin;
+pub use hint::HintPlugin;
+pub use hud::HudPlugin;
+pub use keyboard::KeyboardEntryPlugin;
+pub use overlay::BoardOverlayPlugin;
+pub use picking::PiecePickingPlugin;
+pub use promotion::PromotionPlugin;
+pub use puzzle::PuzzlePlugin;
+pub use review::ReviewPlugin;
+pub use rules::ChessRulesPlugin;
+pub use save::SavePlugin;
+pub use theme::ThemePlugin;
+pub use tray::CapturedPiecesPlugin;
+
+// Everything needed for a playable board, each part can be disabled or replaced through the group builder
+pub struct ChessPlugins;
+
+impl PluginGroup for ChessPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(ChessRulesPlugin::default())
+            .add(PlayerControllerPlugin)
+            .add(ChessBoardRenderPlugin::default())
+            .add(BoardOverlayPlugin::default())
+            .add(ThemePlugin::default())
+            .add(CapturedPiecesPlugin::default())
+            .add(PiecePickingPlugin::default())
+            .add(HudPlugin)
+            .add(KeyboardEntryPlugin)
+            .add(PromotionPlugin::default())
+            .add(OrbitCameraPlugin::default())
+            .add(EditorPlugin)
+            .add(SavePlugin::default())
+            .add(AnalysisPlugin::default())
+            .add(HintPlugin::default())
+            .add(ReviewPlugin::default());
+    }
+}
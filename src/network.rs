@@ -0,0 +1,667 @@
+use bevy::prelude::*;
+use rand::random;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::controller::{Controllers, HumanController, InputSource, NetworkController, PlayerInput};
+use crate::rules::{ChessMove, GameEnded, GameState, LogicChessPiece, MoveApplied, MoveRequested, PieceColor,
+            SharedData, find_uci_move, make_move, move_to_uci, parse_uci};
+
+pub const DEFAULT_PORT: u16 = 7878;
+pub const PROTOCOL_VERSION: u32 = 1;
+// The peer acknowledges a move as soon as it arrives, a peer that doesn't within this time is given up on
+const ACK_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub enum Message {
+    Hello(u32),
+    Welcome(u32, PieceColor),
+    Spectate(u32),
+    Spectating(u32),
+    History(Vec<String>),
+    GameOver(String),
+    Move(usize, String),
+    Ack(usize),
+    Reject(usize, String),
+    Resign,
+    DrawOffer,
+    DrawAccept,
+    DrawDecline,
+    Error(String)
+}
+
+impl Message {
+    fn to_line(&self) -> String {
+        match self {
+            Message::Hello(version) => format!("HELLO rusty_chess {}", version),
+            Message::Welcome(version, color) => format!("WELCOME {} {}", version, color_name(*color)),
+            Message::Spectate(version) => format!("SPECTATE rusty_chess {}", version),
+            Message::Spectating(version) => format!("SPECTATING {}", version),
+            Message::History(moves) => format!("HISTORY {}", moves.join(" ")).trim_end().to_string(),
+            Message::GameOver(text) => format!("GAME_OVER {}", text),
+            Message::Move(ply, uci) => format!("MOVE {} {}", ply, uci),
+            Message::Ack(ply) => format!("ACK {}", ply),
+            Message::Reject(ply, reason) => format!("REJECT {} {}", ply, reason),
+            Message::Resign => "RESIGN".to_string(),
+            Message::DrawOffer => "DRAW_OFFER".to_string(),
+            Message::DrawAccept => "DRAW_ACCEPT".to_string(),
+            Message::DrawDecline => "DRAW_DECLINE".to_string(),
+            Message::Error(text) => format!("ERROR {}", text)
+        }
+    }
+
+    fn parse(line: &str) -> Option<Message> {
+        let mut parts = line.trim().splitn(2, ' ');
+        let command = parts.next()?;
+        let rest = parts.next().unwrap_or("");
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        match (command, args.as_slice()) {
+            ("HELLO", ["rusty_chess", version]) => Some(Message::Hello(version.parse().ok()?)),
+            ("WELCOME", [version, color]) => {
+                Some(Message::Welcome(version.parse().ok()?, parse_color(color)?))
+            },
+            ("SPECTATE", ["rusty_chess", version]) => Some(Message::Spectate(version.parse().ok()?)),
+            ("SPECTATING", [version]) => Some(Message::Spectating(version.parse().ok()?)),
+            ("HISTORY", moves) => Some(Message::History(moves.iter().map(|uci| uci.to_string()).collect())),
+            ("GAME_OVER", _) => Some(Message::GameOver(rest.to_string())),
+            ("MOVE", [ply, uci]) => Some(Message::Move(ply.parse().ok()?, uci.to_string())),
+            ("ACK", [ply]) => Some(Message::Ack(ply.parse().ok()?)),
+            ("REJECT", [ply, ..]) => {
+                let reason = rest.split_once(' ').map_or("", |(_, reason)| reason).to_string();
+                Some(Message::Reject(ply.parse().ok()?, reason))
+            },
+            ("RESIGN", []) => Some(Message::Resign),
+            ("DRAW_OFFER", []) => Some(Message::DrawOffer),
+            ("DRAW_ACCEPT", []) => Some(Message::DrawAccept),
+            ("DRAW_DECLINE", []) => Some(Message::DrawDecline),
+            ("ERROR", _) => Some(Message::Error(rest.to_string())),
+            _ => None
+        }
+    }
+}
+
+pub enum NetworkEvent {
+    Connected(PieceColor),
+    Spectating,
+    SpectatorJoined(TcpStream),
+    Received(Message),
+    Disconnected(String)
+}
+
+enum Client {
+    Player(BufReader<TcpStream>, TcpStream, PieceColor),
+    Spectator(TcpStream)
+}
+
+// The position as this side's rules engine sees it, every move goes through it before reaching the scene
+struct ShadowPosition {
+    board: Vec<Vec<Option<LogicChessPiece>>>,
+    initial_pos: Vec<Vec<bool>>,
+    current_move: PieceColor,
+    history: Vec<String>
+}
+
+pub struct NetworkConnection {
+    outgoing: Mutex<Sender<Message>>,
+    incoming: Mutex<Receiver<NetworkEvent>>,
+    spectators: Vec<TcpStream>,
+    position: Option<ShadowPosition>,
+    connected: bool,
+    spectating: bool,
+    finished: bool,
+    // The ply of the last local move and when it was sent, until the peer acknowledges it
+    awaiting_ack: Option<(usize, Instant)>,
+    draw_offered: bool,
+    peer_offered_draw: bool
+}
+
+impl NetworkConnection {
+    fn new(outgoing: Sender<Message>, incoming: Receiver<NetworkEvent>) -> Self {
+        NetworkConnection {
+            outgoing: Mutex::new(outgoing),
+            incoming: Mutex::new(incoming),
+            spectators: Vec::new(),
+            position: None,
+            connected: false,
+            spectating: false,
+            finished: false,
+            awaiting_ack: None,
+            draw_offered: false,
+            peer_offered_draw: false
+        }
+    }
+
+    fn send(&self, message: Message) {
+        let _ = self.outgoing.lock().unwrap().send(message);
+    }
+
+    fn broadcast(&mut self, message: &Message) {
+        self.spectators.retain(|spectator| {
+            let mut spectator = spectator;
+            writeln!(spectator, "{}", message.to_line()).is_ok()
+        });
+    }
+}
+
+fn color_name(color: PieceColor) -> &'static str {
+    match color {
+        PieceColor::White => "white",
+        PieceColor::Black => "black"
+    }
+}
+
+fn parse_color(text: &str) -> Option<PieceColor> {
+    match text {
+        "white" => Some(PieceColor::White),
+        "black" => Some(PieceColor::Black),
+        _ => None
+    }
+}
+
+fn opposite(color: PieceColor) -> PieceColor {
+    match color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White
+    }
+}
+
+fn read_message(reader: &mut BufReader<TcpStream>) -> Result<Message, String> {
+    let mut line = String::new();
+
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("connection closed".to_string()),
+        Ok(_) => Message::parse(&line).ok_or(format!("unexpected message: {}", line.trim())),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+fn write_message(stream: &mut TcpStream, message: &Message) -> Result<(), String> {
+    writeln!(stream, "{}", message.to_line()).map_err(|e| e.to_string())
+}
+
+fn accept_client(stream: TcpStream, host_color: PieceColor, player_wanted: bool) -> Result<Client, String> {
+    let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+
+    writer.set_read_timeout(Some(Duration::from_secs(5))).map_err(|e| e.to_string())?;
+
+    let (version, spectator) = match read_message(&mut reader)? {
+        Message::Hello(version) => (version, false),
+        Message::Spectate(version) => (version, true),
+        _ => return Err("expected HELLO or SPECTATE".to_string())
+    };
+
+    let refusal = if version != PROTOCOL_VERSION {
+        Some(format!("unsupported protocol version {}", version))
+    } else if !spectator && !player_wanted {
+        Some("the game already has two players, join as a spectator".to_string())
+    } else {
+        None
+    };
+
+    if let Some(text) = refusal {
+        let _ = write_message(&mut writer, &Message::Error(text.clone()));
+        return Err(text);
+    }
+
+    writer.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+    if spectator {
+        write_message(&mut writer, &Message::Spectating(PROTOCOL_VERSION))?;
+        return Ok(Client::Spectator(writer));
+    }
+
+    write_message(&mut writer, &Message::Welcome(PROTOCOL_VERSION, opposite(host_color)))?;
+
+    Ok(Client::Player(reader, writer, host_color))
+}
+
+fn join_handshake(stream: TcpStream, spectator: bool)
+    -> Result<(BufReader<TcpStream>, TcpStream, NetworkEvent), String> {
+    let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+
+    if spectator {
+        write_message(&mut writer, &Message::Spectate(PROTOCOL_VERSION))?;
+    } else {
+        write_message(&mut writer, &Message::Hello(PROTOCOL_VERSION))?;
+    }
+
+    match read_message(&mut reader)? {
+        Message::Welcome(PROTOCOL_VERSION, color) if !spectator => {
+            Ok((reader, writer, NetworkEvent::Connected(color)))
+        },
+        Message::Spectating(PROTOCOL_VERSION) if spectator => {
+            Ok((reader, writer, NetworkEvent::Spectating))
+        },
+        Message::Welcome(version, _) | Message::Spectating(version) => {
+            Err(format!("unsupported protocol version {}", version))
+        },
+        Message::Error(text) => Err(text),
+        _ => Err("unexpected handshake reply".to_string())
+    }
+}
+
+fn run_connection(mut reader: BufReader<TcpStream>, mut writer: TcpStream, connected: NetworkEvent,
+                  events: Sender<NetworkEvent>, messages: Receiver<Message>) {
+    let _ = events.send(connected);
+
+    thread::spawn(move || {
+        loop {
+            match read_message(&mut reader) {
+                Ok(message) => {
+                    if events.send(NetworkEvent::Received(message)).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => {
+                    let _ = events.send(NetworkEvent::Disconnected(e));
+                    break;
+                }
+            }
+        }
+    });
+
+    for message in messages.iter() {
+        if write_message(&mut writer, &message).is_err() {
+            break;
+        }
+    }
+}
+
+pub fn host(port: u16, host_color: Option<PieceColor>) -> NetworkConnection {
+    let (event_sender, event_receiver) = channel();
+    let (message_sender, message_receiver) = channel();
+
+    let host_color = host_color.unwrap_or_else(|| {
+        if random::<bool>() { PieceColor::White } else { PieceColor::Black }
+    });
+
+    thread::spawn(move || {
+        let listener = match TcpListener::bind(("0.0.0.0", port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = event_sender.send(NetworkEvent::Disconnected(e.to_string()));
+                return;
+            }
+        };
+
+        println!("Waiting for an opponent on port {}", port);
+
+        let mut message_receiver = Some(message_receiver);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+
+            match accept_client(stream, host_color, message_receiver.is_some()) {
+                Ok(Client::Player(reader, writer, color)) => {
+                    let events = event_sender.clone();
+                    let messages = message_receiver.take().unwrap();
+
+                    thread::spawn(move || {
+                        run_connection(reader, writer, NetworkEvent::Connected(color), events, messages);
+                    });
+                },
+                Ok(Client::Spectator(writer)) => {
+                    if event_sender.send(NetworkEvent::SpectatorJoined(writer)).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => println!("Refused a connection: {}", e)
+            }
+        }
+    });
+
+    NetworkConnection::new(message_sender, event_receiver)
+}
+
+pub fn join(address: String, spectator: bool) -> NetworkConnection {
+    let (event_sender, event_receiver) = channel();
+    let (message_sender, message_receiver) = channel();
+
+    thread::spawn(move || {
+        let result = TcpStream::connect(&address)
+            .map_err(|e| e.to_string())
+            .and_then(|stream| join_handshake(stream, spectator));
+
+        match result {
+            Ok((reader, writer, connected)) => {
+                run_connection(reader, writer, connected, event_sender, message_receiver);
+            },
+            Err(e) => {
+                let _ = event_sender.send(NetworkEvent::Disconnected(e));
+            }
+        }
+    });
+
+    NetworkConnection::new(message_sender, event_receiver)
+}
+
+impl ShadowPosition {
+    fn new(shared_data: &SharedData) -> Self {
+        ShadowPosition {
+            board: shared_data.board.clone(),
+            initial_pos: shared_data.initial_pos.clone(),
+            current_move: shared_data.current_move,
+            history: shared_data.move_history.clone()
+        }
+    }
+
+    fn play(&mut self, uci: &str) -> Result<ChessMove, String> {
+        parse_uci(uci).ok_or("malformed move")?;
+
+        let chess_move = find_uci_move(&self.board, &self.initial_pos, self.current_move, uci)
+            .ok_or("illegal move")?;
+
+        make_move(&mut self.board, &mut self.initial_pos, &chess_move);
+        self.current_move = opposite(self.current_move);
+        self.history.push(uci.to_string());
+
+        Ok(chess_move)
+    }
+}
+
+fn request_remote_move(connection: &mut NetworkConnection, uci: &str,
+                       inputs: &mut Events<PlayerInput>) -> Result<(), String> {
+    let position = connection.position.as_mut().unwrap();
+    let color = position.current_move;
+    let chess_move = position.play(uci)?;
+
+    inputs.send(PlayerInput {
+        source: InputSource::Network,
+        color,
+        request: MoveRequested {
+            from: chess_move.from,
+            to: chess_move.to,
+            promotion: chess_move.promotion
+        }
+    });
+
+    let ply = position.history.len() - 1;
+    connection.broadcast(&Message::Move(ply, uci.to_string()));
+
+    Ok(())
+}
+
+fn end_game(connection: &mut NetworkConnection, games_ended: &mut Events<GameEnded>, result: &str, reason: &str) {
+    if connection.finished {
+        return;
+    }
+
+    connection.finished = true;
+    games_ended.send(GameEnded { result: result.to_string(), reason: reason.to_string() });
+}
+
+pub fn network_system(keyboard_input: Res<Input<KeyCode>>, mut connection: ResMut<NetworkConnection>,
+                      mut inputs: ResMut<Events<PlayerInput>>, mut controllers: ResMut<Controllers>,
+                      mut applied_reader: Local<EventReader<MoveApplied>>, moves_applied: Res<Events<MoveApplied>>,
+                      mut ended_reader: Local<EventReader<GameEnded>>, mut games_ended: ResMut<Events<GameEnded>>,
+                      mut shared_data: ResMut<SharedData>) {
+    if connection.position.is_none() {
+        connection.position = Some(ShadowPosition::new(&shared_data));
+    }
+
+    if !connection.connected {
+        if let GameState::WaitingForSelect = shared_data.game_state {
+            shared_data.game_state = GameState::Connecting;
+        }
+    }
+
+    for move_applied in applied_reader.iter(&moves_applied) {
+        let position = connection.position.as_mut().unwrap();
+
+        // Moves requested by the peer are already in the shadow position
+        if move_applied.ply < position.history.len() {
+            continue;
+        }
+
+        let uci = move_to_uci(move_applied.from, move_applied.to, move_applied.promotion);
+
+        if position.play(&uci).is_err() {
+            continue;
+        }
+
+        if Some(move_applied.color) == shared_data.local_color {
+            connection.send(Message::Move(move_applied.ply, uci.clone()));
+            connection.awaiting_ack = Some((move_applied.ply, Instant::now()));
+            connection.draw_offered = false;
+        }
+
+        connection.broadcast(&Message::Move(move_applied.ply, uci));
+    }
+
+    for game_ended in ended_reader.iter(&games_ended) {
+        connection.finished = true;
+        connection.broadcast(&Message::GameOver(format!("{} {}", game_ended.result, game_ended.reason)));
+    }
+
+    let events: Vec<NetworkEvent> = connection.incoming.lock().unwrap().try_iter().collect();
+
+    for event in events {
+        match event {
+            NetworkEvent::Connected(color) => {
+                println!("Connected, playing as {}", color_name(color));
+
+                connection.connected = true;
+                shared_data.local_color = Some(color);
+                controllers.set(color, Box::new(HumanController::mouse()));
+                controllers.set(opposite(color), Box::new(NetworkController::default()));
+                shared_data.game_state = GameState::WaitingForSelect;
+            },
+            NetworkEvent::Spectating => {
+                println!("Connected as a spectator");
+
+                connection.connected = true;
+                connection.spectating = true;
+                controllers.set(PieceColor::White, Box::new(NetworkController::default()));
+                controllers.set(PieceColor::Black, Box::new(NetworkController::default()));
+                shared_data.game_state = GameState::WaitingForSelect;
+            },
+            NetworkEvent::SpectatorJoined(mut spectator) => {
+                let history = connection.position.as_ref().unwrap().history.clone();
+
+                if write_message(&mut spectator, &Message::History(history)).is_ok() {
+                    if connection.finished {
+                        let _ = write_message(&mut spectator, &Message::GameOver("* game already finished".to_string()));
+                    }
+
+                    connection.spectators.push(spectator);
+                }
+            },
+            NetworkEvent::Disconnected(reason) => {
+                end_game(&mut connection, &mut games_ended, "*", &format!("connection lost ({})", reason));
+            },
+            NetworkEvent::Received(message) => {
+                if connection.finished {
+                    continue;
+                }
+
+                if connection.spectating {
+                    let moves = match message {
+                        Message::History(moves) => moves,
+                        Message::Move(ply, uci) if ply == connection.position.as_ref().unwrap().history.len() => vec![uci],
+                        Message::GameOver(text) => {
+                            let mut parts = text.splitn(2, ' ');
+                            let result = parts.next().unwrap_or("*").to_string();
+                            let reason = parts.next().unwrap_or("").to_string();
+
+                            end_game(&mut connection, &mut games_ended, &result, &reason);
+                            continue;
+                        },
+                        Message::Error(text) => {
+                            end_game(&mut connection, &mut games_ended, "*", &text);
+                            continue;
+                        },
+                        _ => continue
+                    };
+
+                    for uci in moves.iter() {
+                        if let Err(reason) = request_remote_move(&mut connection, uci, &mut inputs) {
+                            end_game(&mut connection, &mut games_ended, "*",
+                                     &format!("invalid move {} ({})", uci, reason));
+                            break;
+                        }
+                    }
+
+                    continue;
+                }
+
+                let local_color = shared_data.local_color.unwrap_or(PieceColor::White);
+                let peer = color_name(opposite(local_color));
+
+                match message {
+                    Message::Move(ply, uci) => {
+                        let position = connection.position.as_ref().unwrap();
+                        let result = if ply != position.history.len() {
+                            Err("unexpected move number".to_string())
+                        } else if position.current_move == local_color {
+                            Err("not the side to move".to_string())
+                        } else {
+                            request_remote_move(&mut connection, &uci, &mut inputs)
+                        };
+
+                        match result {
+                            Ok(()) => {
+                                connection.peer_offered_draw = false;
+                                connection.send(Message::Ack(ply));
+                            },
+                            Err(reason) => {
+                                connection.send(Message::Reject(ply, reason.clone()));
+                                end_game(&mut connection, &mut games_ended, "*",
+                                         &format!("{} played a rejected move {} ({})", peer, uci, reason));
+                            }
+                        }
+                    },
+                    Message::Ack(ply) if connection.awaiting_ack.map(|(awaited, _)| awaited) == Some(ply) => {
+                        connection.awaiting_ack = None;
+                    },
+                    Message::Reject(ply, reason) => {
+                        end_game(&mut connection, &mut games_ended, "*",
+                                 &format!("{} rejected move {} ({})", peer, ply, reason));
+                    },
+                    Message::Resign => {
+                        let result = if let PieceColor::White = local_color { "1-0" } else { "0-1" };
+                        end_game(&mut connection, &mut games_ended, result, &format!("{} resigned", peer));
+                    },
+                    Message::DrawOffer => {
+                        println!("Opponent offers a draw, press Ctrl+D to accept or Ctrl+N to decline");
+                        connection.peer_offered_draw = true;
+                    },
+                    Message::DrawAccept if connection.draw_offered => {
+                        end_game(&mut connection, &mut games_ended, "1/2-1/2", "draw agreed");
+                    },
+                    Message::DrawDecline => {
+                        println!("Opponent declined the draw");
+                        connection.draw_offered = false;
+                    },
+                    Message::Error(text) => end_game(&mut connection, &mut games_ended, "*", &text),
+                    _ => ()
+                }
+            }
+        }
+    }
+
+    if !connection.connected || connection.spectating || connection.finished {
+        return;
+    }
+
+    let local_color = shared_data.local_color.unwrap_or(PieceColor::White);
+
+    if let Some((ply, sent)) = connection.awaiting_ack {
+        if sent.elapsed() > ACK_TIMEOUT {
+            let reason = format!("{} didn't acknowledge move {}", color_name(opposite(local_color)), ply);
+
+            connection.send(Message::Error(reason.clone()));
+            end_game(&mut connection, &mut games_ended, "*", &reason);
+            return;
+        }
+    }
+
+    // Held with Ctrl, so typing a move like Rd1 can't resign the game
+    let control = keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+
+    if !control {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::R) {
+        let result = if let PieceColor::White = local_color { "0-1" } else { "1-0" };
+
+        connection.send(Message::Resign);
+        end_game(&mut connection, &mut games_ended, result, &format!("{} resigned", color_name(local_color)));
+    } else if keyboard_input.just_pressed(KeyCode::D) {
+        if connection.peer_offered_draw {
+            connection.send(Message::DrawAccept);
+            end_game(&mut connection, &mut games_ended, "1/2-1/2", "draw agreed");
+        } else if !connection.draw_offered {
+            println!("Draw offered");
+            connection.send(Message::DrawOffer);
+            connection.draw_offered = true;
+        }
+    } else if keyboard_input.just_pressed(KeyCode::N) && connection.peer_offered_draw {
+        connection.send(Message::DrawDecline);
+        connection.peer_offered_draw = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = vec![
+            Message::Hello(PROTOCOL_VERSION),
+            Message::Welcome(1, PieceColor::Black),
+            Message::Spectate(1),
+            Message::Spectating(1),
+            Message::History(vec!["e2e4".to_string(), "e7e5".to_string()]),
+            Message::History(Vec::new()),
+            Message::GameOver("1-0 White resigned".to_string()),
+            Message::Move(3, "e7e8q".to_string()),
+            Message::Ack(3),
+            Message::Reject(4, "illegal move e2e5".to_string()),
+            Message::Resign,
+            Message::DrawOffer,
+            Message::DrawAccept,
+            Message::DrawDecline,
+            Message::Error("version mismatch".to_string())
+        ];
+
+        for message in messages.iter() {
+            let line = message.to_line();
+            let parsed = Message::parse(&line).unwrap_or_else(|| panic!("{} doesn't parse", line));
+
+            assert_eq!(parsed.to_line(), line);
+        }
+    }
+
+    #[test]
+    fn parsed_fields() {
+        assert!(matches!(Message::parse("WELCOME 1 white\n"), Some(Message::Welcome(1, PieceColor::White))));
+        assert!(matches!(Message::parse("REJECT 7"), Some(Message::Reject(7, reason)) if reason.is_empty()));
+
+        match Message::parse("HISTORY e2e4 e7e5") {
+            Some(Message::History(moves)) => assert_eq!(moves, ["e2e4", "e7e5"]),
+            _ => panic!("HISTORY should parse")
+        }
+    }
+
+    #[test]
+    fn broken_lines_are_rejected() {
+        assert!(Message::parse("").is_none());
+        assert!(Message::parse("HELLO other_game 1").is_none());
+        assert!(Message::parse("WELCOME 1 red").is_none());
+        assert!(Message::parse("MOVE x e2e4").is_none());
+        assert!(Message::parse("MOVE 1").is_none());
+        assert!(Message::parse("RESIGN now").is_none());
+        assert!(Message::parse("TELEPORT e2e4").is_none());
+    }
+}
@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::pipeline::PrimitiveTopology;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+use bevy_mod_picking::*;
+
+use crate::board::{board_to_global, global_to_board};
+use crate::rules::{BoardPosition, GameState, MoveApplied, PieceType, SharedData, check_mate};
+
+// Size of the generated glow texture in pixels
+const GLOW_SIZE: u32 = 64;
+
+#[derive(Clone)]
+pub struct OverlayConfig {
+    pub last_move_color: Color,
+    pub check_color: Color,
+    pub hover_color: Color
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        OverlayConfig {
+            last_move_color: Color::rgba(1.0, 0.85, 0.2, 0.4),
+            check_color: Color::rgba(1.0, 0.1, 0.1, 0.9),
+            hover_color: Color::rgba(1.0, 1.0, 1.0, 0.8)
+        }
+    }
+}
+
+pub enum Overlay {
+    LastMoveFrom,
+    LastMoveTo,
+    Check,
+    Hover
+}
+
+// The squares of the last move, forgotten when the editor changes the board
+#[derive(Default)]
+pub struct LastMove(pub Option<(BoardPosition, BoardPosition)>);
+
+// A square frame lying on the board, `width` is the thickness of its sides
+fn outline_mesh(size: f32, width: f32) -> Mesh {
+    let (outer, inner) = (size / 2., size / 2. - width);
+    let corners = [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)];
+
+    let mut positions = Vec::new();
+
+    for &(x, z) in corners.iter() {
+        positions.push([x * outer, 0.0, z * outer]);
+        positions.push([x * inner, 0.0, z * inner]);
+    }
+
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    let uvs = vec![[0.0, 0.0]; positions.len()];
+    let mut indices = Vec::new();
+
+    for side in 0..4 {
+        let (outer_a, inner_a) = (side * 2, side * 2 + 1);
+        let (outer_b, inner_b) = ((side * 2 + 2) % 8, (side * 2 + 3) % 8);
+
+        indices.extend_from_slice(&[outer_a, inner_b, outer_b, outer_a, inner_a, inner_b]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh
+}
+
+// White with the alpha fading from the centre, tinted by the material colour
+fn glow_texture() -> Texture {
+    let mut data = Vec::new();
+
+    for y in 0..GLOW_SIZE {
+        for x in 0..GLOW_SIZE {
+            let centre = (GLOW_SIZE - 1) as f32 / 2.;
+            let distance = ((x as f32 - centre).powi(2) + (y as f32 - centre).powi(2)).sqrt() / centre;
+            let alpha = (1. - distance).max(0.).powf(1.5);
+
+            data.extend_from_slice(&[255, 255, 255, (alpha * 255.) as u8]);
+        }
+    }
+
+    Texture::new(Extent3d::new(GLOW_SIZE, GLOW_SIZE, 1), TextureDimension::D2, data,
+                 TextureFormat::Rgba8UnormSrgb)
+}
+
+pub fn setup(commands: &mut Commands, mut meshes: ResMut<Assets<Mesh>>,
+             mut materials: ResMut<Assets<StandardMaterial>>, mut textures: ResMut<Assets<Texture>>,
+             config: Res<OverlayConfig>) {
+    let square = meshes.add(Mesh::from(shape::Plane { size: 1.0 }));
+    let overlays = vec![
+        (Overlay::LastMoveFrom, square.clone(), config.last_move_color, None, 0.005),
+        (Overlay::LastMoveTo, square, config.last_move_color, None, 0.005),
+        (Overlay::Check, meshes.add(Mesh::from(shape::Plane { size: 1.6 })), config.check_color,
+         Some(textures.add(glow_texture())), 0.01),
+        (Overlay::Hover, meshes.add(outline_mesh(1.0, 0.06)), config.hover_color, None, 0.015)
+    ];
+
+    // The overlays lie just above the board, each a little higher so they don't flicker where they meet
+    for (overlay, mesh, color, texture, height) in overlays {
+        commands.spawn(PbrBundle {
+            mesh,
+            material: materials.add(StandardMaterial {
+                albedo: color,
+                albedo_texture: texture,
+                shaded: false
+            }),
+            transform: Transform::from_translation(Vec3::new(0.0, height, 0.0)),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true
+            },
+            ..Default::default()
+        })
+            .with(overlay);
+    }
+}
+
+pub fn last_move_system(mut move_reader: Local<EventReader<MoveApplied>>, moves_applied: Res<Events<MoveApplied>>,
+                        shared_data: Res<SharedData>, mut last_move: ResMut<LastMove>) {
+    for move_applied in move_reader.iter(&moves_applied) {
+        last_move.0 = Some((move_applied.from, move_applied.to));
+    }
+
+    if let GameState::Editing = shared_data.game_state {
+        last_move.0 = None;
+    }
+}
+
+fn place(transform: &mut Transform, visible: &mut Visible, position: Option<BoardPosition>) {
+    visible.is_visible = position.is_some();
+
+    if let Some(position) = position {
+        let height = transform.translation.y;
+
+        transform.translation = board_to_global(position);
+        transform.translation.y = height;
+    }
+}
+
+pub fn overlay_system(shared_data: Res<SharedData>, last_move: Res<LastMove>,
+                      mut query: Query<(&Overlay, &mut Transform, &mut Visible)>) {
+    let color = shared_data.current_move;
+    let king = shared_data.board.iter().enumerate()
+        .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, field)| (x, y, field)))
+        .find(|(_, _, field)| match field {
+            Some(piece) => piece.piece_type == PieceType::King && piece.piece_color == color,
+            None => false
+        })
+        .map(|(x, y, _)| BoardPosition { x: x as u8, y: y as u8 });
+
+    let editing = matches!(shared_data.game_state, GameState::Editing);
+    let check = king.filter(|_| !editing && check_mate(&shared_data.board, color));
+
+    for (overlay, mut transform, mut visible) in query.iter_mut() {
+        let position = match overlay {
+            Overlay::LastMoveFrom => last_move.0.map(|(from, _)| from),
+            Overlay::LastMoveTo => last_move.0.map(|(_, to)| to),
+            Overlay::Check => check,
+            Overlay::Hover => continue
+        };
+
+        place(&mut transform, &mut visible, position);
+    }
+}
+
+// Registered by PiecePickingPlugin, which owns the PickState it reads. Without picking there's no cursor on the
+// board and the outline is never shown.
+pub fn hover_overlay_system(pick_state: Res<PickState>, shared_data: Res<SharedData>,
+                            mut query: Query<(&Overlay, &mut Transform, &mut Visible)>) {
+    let hovering = pick_state.top(Group::default())
+        .and_then(|(_, intersection)| global_to_board(*intersection.position()))
+        .is_some();
+
+    for (overlay, mut transform, mut visible) in query.iter_mut() {
+        if let Overlay::Hover = overlay {
+            place(&mut transform, &mut visible, Some(shared_data.cursor_board_pos).filter(|_| hovering));
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BoardOverlayPlugin {
+    pub config: OverlayConfig
+}
+
+impl Plugin for BoardOverlayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(LastMove::default())
+            .add_startup_system(setup.system())
+            .add_system(last_move_system.system())
+            .add_system(overlay_system.system());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::rules::{ChessMove, GameStatus, LogicChessPiece, MoveKind, PieceColor, PieceType,
+            chess_move_to_uci, check_mate, game_status, legal_moves, make_move, piece_letter,
+            position_to_string};
+
+pub fn move_to_san(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                   chess_move: &ChessMove) -> String {
+    let piece = match board[chess_move.from.y as usize][chess_move.from.x as usize] {
+        Some(piece) => piece,
+        None => return String::new()
+    };
+    let capture = if chess_move.kind == MoveKind::Capture { "x" } else { "" };
+    let destination = position_to_string(chess_move.to);
+
+    let mut san = if chess_move.kind == MoveKind::Castle {
+        if chess_move.to.x == 0 { "O-O-O".to_string() } else { "O-O".to_string() }
+    } else if piece.piece_type == PieceType::Pawn {
+        let file = if capture.is_empty() {
+            String::new()
+        } else {
+            ((b'a' + chess_move.from.x) as char).to_string()
+        };
+        let promotion = match chess_move.promotion {
+            Some(piece_type) => format!("={}", piece_letter(piece_type)),
+            None => String::new()
+        };
+
+        format!("{}{}{}{}", file, capture, destination, promotion)
+    } else {
+        let rivals: Vec<ChessMove> = legal_moves(board, initial_pos, piece.piece_color).into_iter()
+            .filter(|other| {
+                other.kind != MoveKind::Castle
+                    && other.to.x == chess_move.to.x && other.to.y == chess_move.to.y
+                    && !(other.from.x == chess_move.from.x && other.from.y == chess_move.from.y)
+                    && board[other.from.y as usize][other.from.x as usize]
+                        .is_some_and(|other_piece| other_piece.piece_type == piece.piece_type)
+            })
+            .collect();
+
+        let from = position_to_string(chess_move.from);
+        let disambiguation = if rivals.is_empty() {
+            ""
+        } else if rivals.iter().all(|other| other.from.x != chess_move.from.x) {
+            &from[0..1]
+        } else if rivals.iter().all(|other| other.from.y != chess_move.from.y) {
+            &from[1..2]
+        } else {
+            &from[..]
+        };
+
+        format!("{}{}{}{}", piece_letter(piece.piece_type), disambiguation, capture, destination)
+    };
+
+    let (mut board_copy, mut initial_pos_copy) = (board.to_vec(), initial_pos.to_vec());
+    make_move(&mut board_copy, &mut initial_pos_copy, chess_move);
+
+    let enemy_color = match piece.piece_color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White
+    };
+
+    if game_status(&board_copy, &initial_pos_copy, enemy_color) == GameStatus::Checkmate {
+        san.push('#');
+    } else if check_mate(&board_copy, enemy_color) {
+        san.push('+');
+    }
+
+    san
+}
+
+pub fn strip_annotations(san: &str) -> String {
+    san.trim_end_matches(['+', '#', '!', '?'])
+        .replace('0', "O")
+}
+
+pub fn find_move(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                 color: PieceColor, text: &str) -> Result<ChessMove, String> {
+    let text = text.trim();
+    let moves = legal_moves(board, initial_pos, color);
+
+    if let Some(chess_move) = moves.iter().find(|chess_move| chess_move_to_uci(chess_move) == text) {
+        return Ok(*chess_move);
+    }
+
+    let wanted = strip_annotations(text);
+
+    moves.iter()
+        .find(|chess_move| strip_annotations(&move_to_san(board, initial_pos, chess_move)) == wanted)
+        .copied()
+        .ok_or(format!("illegal or unrecognised move: {}", text))
+}
+
+pub fn result_string(status: GameStatus, side_to_move: PieceColor) -> &'static str {
+    match (status, side_to_move) {
+        (GameStatus::Checkmate, PieceColor::White) => "0-1",
+        (GameStatus::Checkmate, PieceColor::Black) => "1-0",
+        (GameStatus::Stalemate, _) => "1/2-1/2",
+        (GameStatus::Ongoing, _) => "*"
+    }
+}
+
+pub fn format_pgn(tags: &[(&str, String)], moves: &[String], result: &str) -> String {
+    format_annotated_pgn(tags, moves, &[], result)
+}
+
+// `annotations` go after the move with the same index, e.g. "$2 {Nf3 was better}"
+pub fn format_annotated_pgn(tags: &[(&str, String)], moves: &[String], annotations: &[String],
+                            result: &str) -> String {
+    format_pgn_from(tags, moves, annotations, result, 0)
+}
+
+// For games set up from a FEN, the first move is numbered as if `plies_before` plies had been played
+pub fn format_pgn_from(tags: &[(&str, String)], moves: &[String], annotations: &[String], result: &str,
+                       plies_before: usize) -> String {
+    let mut pgn = String::new();
+
+    for (name, value) in tags.iter() {
+        pgn += &format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+
+    pgn += &format!("[Result \"{}\"]\n\n", result);
+
+    let mut tokens = Vec::new();
+
+    for (i, san) in moves.iter().enumerate() {
+        let ply = plies_before + i;
+
+        if ply.is_multiple_of(2) {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if i == 0 {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+
+        tokens.push(san.clone());
+
+        // Comments are split into words, so long ones are wrapped like the moves
+        if let Some(annotation) = annotations.get(i) {
+            tokens.extend(annotation.split_whitespace().map(|word| word.to_string()));
+
+            if ply.is_multiple_of(2) && i + 1 < moves.len() && !annotation.is_empty() {
+                tokens.push(format!("{}...", ply / 2 + 1));
+            }
+        }
+    }
+
+    tokens.push(result.to_string());
+
+    let mut line_length = 0;
+
+    for token in tokens.iter() {
+        if line_length > 0 && line_length + token.len() + 1 > 80 {
+            pgn.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            pgn.push(' ');
+            line_length += 1;
+        }
+
+        pgn += token;
+        line_length += token.len();
+    }
+
+    pgn.push('\n');
+    pgn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::{FenPosition, START_FEN, parse_fen};
+
+    fn san(position: &FenPosition, text: &str) -> String {
+        let chess_move = find_move(&position.board, &position.initial_pos, position.current_move, text).unwrap();
+
+        move_to_san(&position.board, &position.initial_pos, &chess_move)
+    }
+
+    #[test]
+    fn plain_moves() {
+        let start = parse_fen(START_FEN).unwrap();
+
+        assert_eq!(san(&start, "e2e4"), "e4");
+        assert_eq!(san(&start, "g1f3"), "Nf3");
+        assert_eq!(san(&parse_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap(), "e4d5"), "exd5");
+    }
+
+    #[test]
+    fn disambiguation() {
+        let files = parse_fen("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1").unwrap();
+        let ranks = parse_fen("4k3/8/8/8/8/1N6/8/1N2K3 w - - 0 1").unwrap();
+
+        assert_eq!(san(&files, "b1d2"), "Nbd2");
+        assert_eq!(san(&files, "f3d2"), "Nfd2");
+        assert_eq!(san(&ranks, "b1d2"), "N1d2");
+        assert_eq!(san(&ranks, "b3d2"), "N3d2");
+    }
+
+    #[test]
+    fn castling_promotion_check_and_mate() {
+        let castling = parse_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+
+        assert_eq!(san(&castling, "O-O"), "O-O");
+        assert_eq!(san(&castling, "0-0-0"), "O-O-O");
+        assert_eq!(san(&parse_fen("8/P7/8/8/8/8/8/k6K w - - 0 1").unwrap(), "a7a8q"), "a8=Q+");
+        assert_eq!(san(&parse_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap(), "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn find_move_reads_uci_and_annotated_san() {
+        let start = parse_fen(START_FEN).unwrap();
+        let find = |text: &str| find_move(&start.board, &start.initial_pos, start.current_move, text)
+            .map(|chess_move| chess_move_to_uci(&chess_move));
+
+        assert_eq!(find("g1f3"), Ok("g1f3".to_string()));
+        assert_eq!(find("Nf3!?"), Ok("g1f3".to_string()));
+        assert_eq!(find(" e4 "), Ok("e2e4".to_string()));
+        assert!(find("Nf4").is_err());
+        assert!(find("e5").is_err());
+    }
+
+    #[test]
+    fn numbering_from_a_position_with_black_to_move() {
+        let position = parse_fen("4k3/8/8/8/8/8/8/4K3 b - - 7 20").unwrap();
+        let moves = ["Kd7".to_string(), "Ke2".to_string(), "Kc6".to_string()];
+        let pgn = format_pgn_from(&[("Event", "Test".to_string())], &moves, &[], "1/2-1/2", position.plies_before());
+
+        assert_eq!(pgn, "[Event \"Test\"]\n[Result \"1/2-1/2\"]\n\n20... Kd7 21. Ke2 Kc6 1/2-1/2\n");
+    }
+
+    #[test]
+    fn annotations_follow_their_move() {
+        let moves = ["e4".to_string(), "e5".to_string(), "Qh5".to_string()];
+        let annotations = ["$2 {Nf3 was better}".to_string(), String::new(), "$1".to_string()];
+        let pgn = format_annotated_pgn(&[], &moves, &annotations, "*");
+
+        assert_eq!(pgn, "[Result \"*\"]\n\n1. e4 $2 {Nf3 was better} 1... e5 2. Qh5 $1 *\n");
+    }
+
+    #[test]
+    fn tags_are_escaped() {
+        let pgn = format_pgn(&[("White", "say \"hi\" \\o/".to_string())], &[], "*");
+
+        assert_eq!(pgn, "[White \"say \\\"hi\\\" \\\\o/\"]\n[Result \"*\"]\n\n*\n");
+    }
+}
@@ -0,0 +1,305 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_mod_picking::*;
+
+use crate::board::{ChessBoard, ChessPiece, Meshes, PieceMaterials, board_to_global, global_to_board};
+use crate::controller::{Controllers, InputSource, PlayerInput};
+use crate::overlay::hover_overlay_system;
+use crate::rules::{BoardPosition, GameState, MoveRequested, PieceColor, PieceType, SharedData};
+
+pub struct SelectedPiece;
+
+#[derive(Clone)]
+pub struct PickingConfig {
+    // Pieces can also be dragged to their square, clicking the piece and then the square always works
+    pub drag_and_drop: bool,
+    pub drag_height: f32,
+    // How fast a piece dropped on an illegal square slides back, in squares per second
+    pub return_speed: f32
+}
+
+impl Default for PickingConfig {
+    fn default() -> Self {
+        PickingConfig {
+            drag_and_drop: true,
+            drag_height: 0.5,
+            return_speed: 12.
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DragState {
+    // The piece being dragged and the square it was picked up from
+    pub piece: Option<(Entity, BoardPosition)>,
+    // Whether the cursor was over the board in the last frame of the drag
+    over_board: bool,
+    // The piece dropped this frame, it stays on the target square until the move has been checked
+    dropped: Option<Entity>
+}
+
+fn unselect(commands: &mut Commands, entity: Entity, piece_materials: &PieceMaterials, color: PieceColor) {
+    commands.remove_one::<SelectedPiece>(entity);
+    commands.insert_one(entity, piece_materials.get(color));
+}
+
+// Lets the picking work with whatever 3D camera the application spawns
+pub fn pick_source_system(commands: &mut Commands,
+                          query: Query<(Entity, &Camera), Without<PickSource>>) {
+    for (entity, camera) in query.iter() {
+        if camera.name.as_deref() == Some(CAMERA_3D) {
+            commands.insert_one(entity, PickSource::default());
+        }
+    }
+}
+
+pub fn piece_raycast_system(
+    commands: &mut Commands,
+    mut query: Query<(&InteractableMesh, Entity, &PieceColor), With<ChessPiece>>,
+    query2: Query<(Entity, &PieceColor), With<SelectedPiece>>,
+    piece_materials: Res<PieceMaterials>, controllers: Res<Controllers>, drag: Res<DragState>,
+    mut shared_data: ResMut<SharedData>) {
+
+    // A dropped piece is still under the cursor, the release mustn't select it
+    if drag.dropped.is_some() {
+        return;
+    }
+
+    for (interactable, entity, piece_color) in &mut query.iter_mut() {
+        let mouse_down_event = interactable
+            .mouse_down_event(&Group::default(), MouseButton::Left)
+            .unwrap();
+
+        if mouse_down_event.is_none() || piece_color != &shared_data.current_move
+            || !controllers.get(*piece_color).accepts(InputSource::Mouse) {
+            continue;
+        }
+
+        if let MouseDownEvents::MouseJustReleased = mouse_down_event {
+            if let GameState::PieceSelected = shared_data.game_state {
+                for (entity, piece_color) in query2.iter() {
+                    unselect(commands, entity, &piece_materials, *piece_color);
+                }
+
+                shared_data.game_state = GameState::WaitingForSelect;
+            }
+
+            match shared_data.game_state {
+                GameState::PieceSelected | GameState::WaitingForSelect => {
+                    shared_data.game_state = GameState::PieceSelected;
+                    commands.insert(entity, (SelectedPiece, piece_materials.selected.clone()));
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+pub fn board_raycast_system(
+    commands: &mut Commands,
+    mut query: Query<(&InteractableMesh, ), With<ChessBoard>>,
+    mut query2: Query<(Entity, &BoardPosition, &PieceColor), With<SelectedPiece>>,
+    piece_materials: Res<PieceMaterials>, mut inputs: ResMut<Events<PlayerInput>>, mut shared_data: ResMut<SharedData>) {
+
+    if let GameState::PieceSelected = shared_data.game_state {} else {
+        return;
+    }
+
+    let mut flag = false;
+
+    for (interactable, ) in query.iter_mut() {
+        let mouse_down_event = interactable
+            .mouse_down_event(&Group::default(), MouseButton::Left)
+            .unwrap();
+
+        if let MouseDownEvents::MouseJustReleased = mouse_down_event {
+            flag = true;
+        }
+    }
+
+    if !flag {
+        return;
+    }
+
+    shared_data.game_state = GameState::WaitingForSelect;
+
+    for (entity, board_position, piece_color) in query2.iter_mut() {
+        unselect(commands, entity, &piece_materials, *piece_color);
+
+        inputs.send(PlayerInput {
+            source: InputSource::Mouse,
+            color: *piece_color,
+            request: MoveRequested {
+                from: *board_position,
+                to: shared_data.cursor_board_pos,
+                promotion: None
+            }
+        });
+    }
+}
+
+// The piece being dragged is always under the cursor, so it's skipped and the square is taken from
+// whatever is below it
+fn cursor_pick(pick_state: &PickState, drag: &DragState) -> Option<Vec3> {
+    let dragged = drag.piece.map(|(entity, _)| entity);
+
+    pick_state.list(Group::default())
+        .and_then(|list| list.iter().find(|(entity, _)| Some(*entity) != dragged))
+        .map(|(_, intersection)| *intersection.position())
+}
+
+pub fn get_board_pos(
+    pick_state: Res<PickState>,
+    drag: Res<DragState>,
+    mut shared_data: ResMut<SharedData>
+) {
+    if let Some(board_pos) = cursor_pick(&pick_state, &drag).and_then(global_to_board) {
+        shared_data.cursor_board_pos = board_pos;
+    }
+}
+
+// Pressing the mouse on a piece picks it up, it follows the cursor until the button is released
+// and is then moved to the square under the cursor. Releasing it on its own square selects it like a click.
+pub fn piece_drag_system(
+    commands: &mut Commands,
+    pick_state: Res<PickState>, mouse_input: Res<Input<MouseButton>>,
+    pieces: Query<(Entity, &InteractableMesh, &BoardPosition, &PieceColor), With<ChessPiece>>,
+    selected: Query<(Entity, &PieceColor), With<SelectedPiece>>,
+    mut transforms: Query<(&mut Transform, &PieceType), With<ChessPiece>>,
+    meshes: Res<Meshes>, piece_materials: Res<PieceMaterials>, config: Res<PickingConfig>, controllers: Res<Controllers>,
+    mut drag: ResMut<DragState>,
+    mut inputs: ResMut<Events<PlayerInput>>, mut shared_data: ResMut<SharedData>) {
+
+    if !config.drag_and_drop {
+        return;
+    }
+
+    let playing = matches!(shared_data.game_state, GameState::WaitingForSelect | GameState::PieceSelected);
+
+    let (entity, origin) = match drag.piece {
+        Some(piece) => piece,
+        None => {
+            if !playing {
+                return;
+            }
+
+            for (entity, interactable, board_position, piece_color) in pieces.iter() {
+                let mouse_down_event = interactable
+                    .mouse_down_event(&Group::default(), MouseButton::Left)
+                    .unwrap();
+
+                if let MouseDownEvents::MouseJustPressed = mouse_down_event {
+                    if *piece_color == shared_data.current_move
+                        && controllers.get(*piece_color).accepts(InputSource::Mouse) {
+                        drag.piece = Some((entity, *board_position));
+                        drag.over_board = false;
+                    }
+                }
+            }
+
+            return;
+        }
+    };
+
+    let (mut transform, piece_type) = match transforms.get_mut(entity) {
+        Ok(piece) => piece,
+        Err(_) => {
+            // The piece was taken off the board during the drag
+            drag.piece = None;
+            return;
+        }
+    };
+
+    if playing && mouse_input.pressed(MouseButton::Left) {
+        let point = cursor_pick(&pick_state, &drag);
+
+        drag.over_board = point.and_then(global_to_board).is_some();
+
+        if let Some(point) = point {
+            let height = meshes.set.model(*piece_type).height;
+
+            transform.translation = Vec3::new(point.x, config.drag_height + height, point.z);
+        }
+
+        return;
+    }
+
+    drag.piece = None;
+
+    let target = shared_data.cursor_board_pos;
+
+    // Anything but a drop on another square leaves the piece to slide back to its own square
+    if !playing || !drag.over_board || target == origin {
+        return;
+    }
+
+    for (entity, piece_color) in selected.iter() {
+        unselect(commands, entity, &piece_materials, *piece_color);
+    }
+
+    transform.translation = meshes.piece_transform(*piece_type, shared_data.current_move,
+                                                   board_to_global(target)).translation;
+    shared_data.game_state = GameState::WaitingForSelect;
+    drag.dropped = Some(entity);
+
+    inputs.send(PlayerInput {
+        source: InputSource::Mouse,
+        color: shared_data.current_move,
+        request: MoveRequested {
+            from: origin,
+            to: target,
+            promotion: None
+        }
+    });
+}
+
+// Pieces that aren't on their square, like the ones dropped on illegal squares, slide back to it.
+// Runs after the board sync, so a legal drop has already been moved to its new square.
+pub fn piece_return_system(
+    time: Res<Time>, config: Res<PickingConfig>, meshes: Res<Meshes>, mut drag: ResMut<DragState>,
+    shared_data: Res<SharedData>,
+    mut query: Query<(Entity, &BoardPosition, &PieceType, &PieceColor, &mut Transform), With<ChessPiece>>) {
+
+    let dropped = drag.dropped.take();
+    let dragged = drag.piece.map(|(entity, _)| entity);
+    // A pawn waiting for its promotion piece stays on the last rank
+    let promoting = shared_data.pending_promotion.map(|chess_move| chess_move.from);
+    let step = config.return_speed * time.delta_seconds();
+
+    for (entity, board_position, piece_type, piece_color, mut transform) in query.iter_mut() {
+        if Some(entity) == dropped || Some(entity) == dragged || Some(*board_position) == promoting {
+            continue;
+        }
+
+        let target = meshes.piece_transform(*piece_type, *piece_color, board_to_global(*board_position)).translation;
+        let offset = target - transform.translation;
+
+        if offset.length() <= step {
+            transform.translation = target;
+        } else {
+            transform.translation += offset.normalize() * step;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PiecePickingPlugin {
+    pub config: PickingConfig
+}
+
+impl Plugin for PiecePickingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(PickingPlugin)
+            .add_plugin(InteractablePickingPlugin)
+            .add_resource(self.config.clone())
+            .add_resource(DragState::default())
+            .add_system(pick_source_system.system())
+            .add_system(get_board_pos.system())
+            .add_system(piece_drag_system.system())
+            .add_system(piece_raycast_system.system())
+            .add_system(board_raycast_system.system())
+            .add_system(hover_overlay_system.system())
+            .add_system_to_stage(stage::POST_UPDATE, piece_return_system.system());
+    }
+}
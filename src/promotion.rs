@@ -0,0 +1,204 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_mod_picking::*;
+use std::f32::consts::PI;
+
+use crate::board::{Meshes, PieceMaterials, board_to_global, piece_mesh};
+use crate::rules::{GameState, PieceType, PromotionCancelled, PromotionChosen, SharedData};
+
+const PIECE_TYPES: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+#[derive(Clone)]
+pub struct PromotionConfig {
+    // Pawns become queens straight away, without asking
+    pub auto_queen: bool,
+    // How far the selector floats above the promotion square and how far it's pulled towards the camera
+    pub height: f32,
+    pub distance: f32
+}
+
+impl Default for PromotionConfig {
+    fn default() -> Self {
+        PromotionConfig {
+            auto_queen: false,
+            height: 1.5,
+            distance: 2.
+        }
+    }
+}
+
+// The piece the next promotion is made to, chosen ahead of time so a game against the clock,
+// an engine or a network player doesn't wait for the selector. None asks every time.
+pub struct PresetPromotion(pub Option<PieceType>);
+
+pub struct PromotionSelector;
+
+fn piece_name(piece_type: Option<PieceType>) -> &'static str {
+    match piece_type {
+        Some(PieceType::Queen) => "queen",
+        Some(PieceType::Rook) => "rook",
+        Some(PieceType::Bishop) => "bishop",
+        Some(PieceType::Knight) => "knight",
+        _ => "ask"
+    }
+}
+
+pub fn setup(commands: &mut Commands, config: Res<PromotionConfig>) {
+    commands.insert_resource(PresetPromotion(if config.auto_queen { Some(PieceType::Queen) } else { None }));
+}
+
+// P goes through ask, queen, rook, bishop and knight
+pub fn preset_system(keyboard_input: Res<Input<KeyCode>>, mut preset: ResMut<PresetPromotion>) {
+    if !keyboard_input.just_pressed(KeyCode::P) {
+        return;
+    }
+
+    preset.0 = match preset.0 {
+        None => Some(PieceType::Queen),
+        Some(PieceType::Queen) => Some(PieceType::Rook),
+        Some(PieceType::Rook) => Some(PieceType::Bishop),
+        Some(PieceType::Bishop) => Some(PieceType::Knight),
+        _ => None
+    };
+
+    println!("Promotion: {}", piece_name(preset.0));
+}
+
+pub fn spawn_promotion_selector(commands: &mut Commands, piece_materials: Res<PieceMaterials>, meshes: Res<Meshes>,
+                                preset: Res<PresetPromotion>, mut promotions: ResMut<Events<PromotionChosen>>,
+                                mut shared_data: ResMut<SharedData>) {
+    let (board_position, piece_color) = match shared_data.game_state {
+        GameState::SpawnPromotionSelector(board_position, piece_color) => {
+            (board_position, piece_color)
+        },
+        _ => return
+    };
+
+    shared_data.game_state = GameState::PawnPromoting(board_position, piece_color);
+
+    if let Some(piece_type) = preset.0 {
+        promotions.send(PromotionChosen { piece_type });
+        return;
+    }
+
+    // The selector is put in front of the camera by selector_position_system
+    for piece_type in PIECE_TYPES.iter() {
+        commands.spawn(PbrBundle {
+                mesh: piece_mesh(&meshes, *piece_type),
+                material: piece_materials.get(piece_color),
+                transform: meshes.piece_transform(*piece_type, piece_color, board_to_global(board_position)),
+                ..Default::default()})
+                .with(PickableMesh::default())
+                .with(InteractableMesh::default())
+                .with(*piece_type)
+                .with(PromotionSelector);
+    }
+}
+
+// Q, R, B and N choose a piece, Escape takes the pawn move back.
+// The keys are used up, so the network keys sharing them don't fire too.
+pub fn selector_system(commands: &mut Commands, mut keyboard_input: ResMut<Input<KeyCode>>,
+                       query: Query<(&InteractableMesh, &PieceType, Entity), With<PromotionSelector>>,
+                       mut promotions: ResMut<Events<PromotionChosen>>,
+                       mut cancels: ResMut<Events<PromotionCancelled>>, shared_data: Res<SharedData>) {
+
+    if let GameState::PawnPromoting(_, _) = shared_data.game_state {} else {
+        // Whatever ended the promotion, like a resignation, takes the selector with it
+        for (_, _, entity) in query.iter() {
+            commands.despawn(entity);
+        }
+
+        return;
+    }
+
+    let mut selected = None;
+    let mut cancelled = false;
+
+    for (interactable, selected_piece_type, _) in query.iter() {
+        let mouse_down_event = interactable
+            .mouse_down_event(&Group::default(), MouseButton::Left)
+            .unwrap();
+
+        if let MouseDownEvents::MouseJustReleased = mouse_down_event {
+            selected = Some(*selected_piece_type);
+        }
+    }
+
+    let keys = [(KeyCode::Q, PieceType::Queen), (KeyCode::R, PieceType::Rook),
+                (KeyCode::B, PieceType::Bishop), (KeyCode::N, PieceType::Knight)];
+
+    for (key, piece_type) in keys.iter() {
+        if keyboard_input.just_pressed(*key) {
+            keyboard_input.reset(*key);
+            selected = Some(*piece_type);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        keyboard_input.reset(KeyCode::Escape);
+        cancelled = true;
+    }
+
+    if selected.is_none() && !cancelled {
+        return;
+    }
+
+    for (_, _, entity) in query.iter() {
+        commands.despawn(entity);
+    }
+
+    match selected {
+        Some(piece_type) => promotions.send(PromotionChosen { piece_type }),
+        None => cancels.send(PromotionCancelled)
+    }
+}
+
+// The selector floats above the promotion square, between it and the camera, and faces the camera,
+// so nothing on the board hides it whichever way the board is looked at
+pub fn selector_position_system(
+    config: Res<PromotionConfig>, meshes: Res<Meshes>, shared_data: Res<SharedData>,
+    mut queries: QuerySet<(Query<(&Transform, &Camera)>,
+                           Query<(&PieceType, &mut Transform), With<PromotionSelector>>)>) {
+
+    let board_position = match shared_data.game_state {
+        GameState::PawnPromoting(board_position, _) => board_position,
+        _ => return
+    };
+
+    let camera_rotation = match queries.q0().iter()
+        .find(|(_, camera)| camera.name.as_deref() == Some(CAMERA_3D)) {
+        Some((transform, _)) => transform.rotation,
+        None => return
+    };
+
+    let right = camera_rotation * Vec3::unit_x();
+    let up = camera_rotation * Vec3::unit_y();
+    let backward = camera_rotation * Vec3::unit_z();
+    let center = board_to_global(board_position) + Vec3::new(0.0, config.height, 0.0) + backward * config.distance;
+
+    for (piece_type, mut transform) in queries.q1_mut().iter_mut() {
+        let model = meshes.set.model(*piece_type);
+        let slot = PIECE_TYPES.iter().position(|other| other == piece_type).unwrap_or(0) as f32;
+
+        transform.translation = center + right * (slot - 1.5) + up * model.height;
+        // Turned round, the front of a piece looks back at the camera
+        transform.rotation = camera_rotation * Quat::from_rotation_y(PI + model.rotation.to_radians());
+    }
+}
+
+#[derive(Default)]
+pub struct PromotionPlugin {
+    pub config: PromotionConfig
+}
+
+impl Plugin for PromotionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_startup_system(setup.system())
+            .add_system(preset_system.system())
+            .add_system(spawn_promotion_selector.system())
+            .add_system(selector_system.system())
+            .add_system(selector_position_system.system());
+    }
+}
@@ -0,0 +1,472 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::camera::{CameraPreset, OrbitCamera, OrbitCameraConfig};
+use crate::controller::{ControllerMove, Controllers, GameView, HumanController, PlayerController};
+use crate::fen::{format_fen, parse_fen};
+use crate::hud;
+use crate::pgn::{find_move, move_to_san};
+use crate::rules::{GameState, GameStatus, MoveApplied, MoveRequested, PieceColor, SharedData,
+                   chess_move_to_uci, game_status, make_move, print_board};
+
+pub const DEFAULT_PUZZLE_RATING: f32 = 1500.;
+
+// How far the rating moves after a puzzle, as in Elo
+const RATING_FACTOR: f32 = 32.;
+
+// The opponent waits this long before replying, so the reply can be followed on the board
+const REPLY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+pub struct PuzzleConfig {
+    pub puzzles_path: String,
+    // Solved and failed puzzles and the rating are kept here from one session to the next
+    pub stats_path: String
+}
+
+impl Default for PuzzleConfig {
+    fn default() -> Self {
+        PuzzleConfig {
+            puzzles_path: "puzzles.txt".to_string(),
+            stats_path: "saves/puzzles.ron".to_string()
+        }
+    }
+}
+
+pub struct Puzzle {
+    pub fen: String,
+    // The player's moves and the opponent's replies in turn in UCI notation, the player moves first
+    pub solution: Vec<String>,
+    pub solution_san: Vec<String>,
+    pub rating: f32
+}
+
+// One puzzle per line: the FEN, the solution in SAN or UCI and optionally the puzzle's rating, split by `|`:
+// r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4 | Qxf7# | 600
+pub fn parse_puzzle(line: &str) -> Result<Puzzle, String> {
+    let fields: Vec<&str> = line.split('|').map(|field| field.trim()).collect();
+
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err("a puzzle needs a FEN, a solution and maybe a rating, split by |".to_string());
+    }
+
+    let mut position = parse_fen(fields[0])?;
+    let mut color = position.current_move;
+    let (mut solution, mut solution_san) = (Vec::new(), Vec::new());
+
+    // Played through once, so a broken solution is found when the file is read
+    for text in fields[1].split_whitespace() {
+        let chess_move = find_move(&position.board, &position.initial_pos, color, text)?;
+
+        solution.push(chess_move_to_uci(&chess_move));
+        solution_san.push(move_to_san(&position.board, &position.initial_pos, &chess_move));
+        make_move(&mut position.board, &mut position.initial_pos, &chess_move);
+        color = if let PieceColor::White = color { PieceColor::Black } else { PieceColor::White };
+    }
+
+    if solution.is_empty() {
+        return Err("the solution has no moves".to_string());
+    }
+
+    // The player's move is the last, a solution ending on a reply could never be finished
+    if solution.len().is_multiple_of(2) {
+        return Err("the solution has to end with the player's move".to_string());
+    }
+
+    let rating = match fields.get(2) {
+        Some(rating) => rating.parse().map_err(|_| format!("invalid puzzle rating: {}", rating))?,
+        None => DEFAULT_PUZZLE_RATING
+    };
+
+    Ok(Puzzle { fen: fields[0].to_string(), solution, solution_san, rating })
+}
+
+// Empty lines and lines starting with # are skipped
+pub fn load_puzzles(path: &str) -> Result<Vec<Puzzle>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut puzzles = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        puzzles.push(parse_puzzle(line).map_err(|e| format!("{} line {}: {}", path, i + 1, e))?);
+    }
+
+    if puzzles.is_empty() {
+        return Err(format!("{} holds no puzzles", path));
+    }
+
+    Ok(puzzles)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PuzzleStats {
+    pub rating: f32,
+    pub solved: u32,
+    pub failed: u32,
+    // The puzzle to start with next time, counted from the start of the file
+    pub next: usize
+}
+
+impl Default for PuzzleStats {
+    fn default() -> Self {
+        PuzzleStats {
+            rating: DEFAULT_PUZZLE_RATING,
+            solved: 0,
+            failed: 0,
+            next: 0
+        }
+    }
+}
+
+impl PuzzleStats {
+    // The player and the puzzle are rated like two players of a game the player won when it was solved
+    pub fn record(&mut self, puzzle_rating: f32, solved: bool) {
+        let expected = 1. / (1. + 10f32.powf((puzzle_rating - self.rating) / 400.));
+        let score = if solved { 1. } else { 0. };
+
+        self.rating += RATING_FACTOR * (score - expected);
+
+        if solved {
+            self.solved += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+}
+
+fn save_stats(path: &str, stats: &PuzzleStats) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(stats, ron::ser::PrettyConfig::new())
+        .map_err(|e| format!("can't write {}: {}", path, e))?;
+
+    if let Some(directory) = Path::new(path).parent() {
+        fs::create_dir_all(directory).map_err(|e| format!("can't create {}: {}", directory.display(), e))?;
+    }
+
+    fs::write(path, text).map_err(|e| format!("can't write {}: {}", path, e))
+}
+
+fn load_stats(path: &str) -> Result<PuzzleStats, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+
+    ron::de::from_str(&text).map_err(|e| format!("can't parse {}: {}", path, e))
+}
+
+// Plays the opponent's moves of the solution, it's only asked while the player's moves were right
+pub struct PuzzleController {
+    solution: Vec<String>,
+    // The ply the reply is for and when it was first asked for
+    waiting: Option<(usize, Instant)>
+}
+
+impl PlayerController for PuzzleController {
+    fn description(&self) -> String {
+        "puzzle".to_string()
+    }
+
+    fn poll(&mut self, view: &GameView) -> ControllerMove {
+        let ply = view.history.len();
+
+        match self.waiting {
+            Some((waiting_ply, since)) if waiting_ply == ply => {
+                if since.elapsed() < REPLY_DELAY {
+                    return ControllerMove::Waiting;
+                }
+            },
+            _ => {
+                self.waiting = Some((ply, Instant::now()));
+                return ControllerMove::Waiting;
+            }
+        }
+
+        let uci = match self.solution.get(ply) {
+            Some(uci) => uci,
+            None => return ControllerMove::Exhausted
+        };
+
+        match find_move(view.board, view.initial_pos, view.current_move, uci) {
+            Ok(chess_move) => ControllerMove::Ready(MoveRequested {
+                from: chess_move.from,
+                to: chess_move.to,
+                promotion: chess_move.promotion
+            }),
+            Err(e) => ControllerMove::Failed(e)
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PuzzleState {
+    puzzles: Vec<Puzzle>,
+    stats: PuzzleStats,
+    current: usize,
+    // Set when the current puzzle is to be put on the board
+    start: bool,
+    // Whether the current puzzle was solved, None while it's being solved
+    solved: Option<bool>,
+    // Only the first try of a puzzle counts for the rating
+    rated: bool,
+    // The side the camera turns to when a puzzle starts
+    face: Option<PieceColor>,
+    texts: [String; 2]
+}
+
+impl PuzzleState {
+    fn status_line(&self) -> String {
+        format!("Rating {:.0}, solved {}, failed {}", self.stats.rating, self.stats.solved, self.stats.failed)
+    }
+
+    // Records the first result of the puzzle and stops the board
+    fn finish(&mut self, solved: bool, config: &PuzzleConfig, shared_data: &mut SharedData) {
+        let puzzle = &self.puzzles[self.current];
+
+        self.texts[0] = if solved {
+            "Solved! N for the next puzzle, R to try again".to_string()
+        } else {
+            format!("Wrong, the solution was {}. N for the next puzzle, R to try again",
+                    puzzle.solution_san.join(" "))
+        };
+
+        if !self.rated {
+            self.stats.record(puzzle.rating, solved);
+            self.rated = true;
+
+            if let Err(e) = save_stats(&config.stats_path, &self.stats) {
+                println!("Puzzles: {}", e);
+            }
+        }
+
+        println!("Puzzles: {}, {}", if solved { "solved" } else { "failed" }, self.status_line());
+
+        self.texts[1] = self.status_line();
+        self.solved = Some(solved);
+        shared_data.game_state = GameState::GameOver;
+    }
+}
+
+pub struct PuzzleText(usize);
+
+pub fn setup(commands: &mut Commands, config: Res<PuzzleConfig>, asset_server: Res<AssetServer>,
+             mut state: ResMut<PuzzleState>) {
+    match load_puzzles(&config.puzzles_path) {
+        Ok(puzzles) => {
+            println!("Puzzles: {} puzzles loaded from {}", puzzles.len(), config.puzzles_path);
+            state.puzzles = puzzles;
+            state.start = true;
+        },
+        Err(e) => {
+            println!("Puzzles: {}", e);
+            state.texts[0] = format!("No puzzles: {}", e);
+        }
+    }
+
+    // A missing file is a first session
+    state.stats = load_stats(&config.stats_path).unwrap_or_default();
+    state.current = state.stats.next % state.puzzles.len().max(1);
+
+    for i in 0..2 {
+        commands.spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    top: Val::Px(10. + i as f32 * 26.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: state.texts[i].clone(),
+                font: asset_server.load(hud::FONT),
+                style: TextStyle {
+                    font_size: 20.,
+                    color: Color::WHITE,
+                    ..Default::default()
+                }
+            },
+            ..Default::default()
+        })
+            .with(PuzzleText(i));
+    }
+}
+
+// Sets up the puzzles one after another and checks every move of the player against the solution.
+// A move that mates solves the puzzle even when the solution mates differently.
+pub fn puzzle_system(keyboard_input: Res<Input<KeyCode>>, config: Res<PuzzleConfig>,
+                     mut applied_reader: Local<EventReader<MoveApplied>>, moves_applied: Res<Events<MoveApplied>>,
+                     mut state: ResMut<PuzzleState>, mut shared_data: ResMut<SharedData>,
+                     mut controllers: ResMut<Controllers>, mut query: Query<(&mut Text, &PuzzleText)>) {
+    if state.puzzles.is_empty() {
+        return;
+    }
+
+    if state.solved.is_some() && keyboard_input.just_pressed(KeyCode::N) {
+        state.current = (state.current + 1) % state.puzzles.len();
+        state.stats.next = state.current;
+        state.rated = false;
+        state.start = true;
+
+        if let Err(e) = save_stats(&config.stats_path, &state.stats) {
+            println!("Puzzles: {}", e);
+        }
+    } else if state.solved.is_some() && keyboard_input.just_pressed(KeyCode::R) {
+        state.start = true;
+    }
+
+    if state.start {
+        state.start = false;
+        state.solved = None;
+
+        let (number, count) = (state.current + 1, state.puzzles.len());
+        let puzzle = &state.puzzles[state.current];
+        let (solution, rating) = (puzzle.solution.clone(), puzzle.rating);
+        // Checked when the file was read
+        let position = parse_fen(&puzzle.fen).unwrap();
+        let color = position.current_move;
+
+        shared_data.start = Some(format_fen(&position.board, &position.initial_pos, color, position.halfmove_clock,
+                                            position.fullmove_number));
+        shared_data.board = position.board;
+        shared_data.initial_pos = position.initial_pos;
+        shared_data.current_move = color;
+        shared_data.move_history = Vec::new();
+        shared_data.pending_promotion = None;
+        shared_data.game_state = GameState::WaitingForSelect;
+
+        let opponent = if let PieceColor::White = color { PieceColor::Black } else { PieceColor::White };
+
+        controllers.set(color, Box::new(HumanController::mouse()));
+        controllers.set(opponent, Box::new(PuzzleController { solution, waiting: None }));
+
+        let side = if let PieceColor::White = color { "White" } else { "Black" };
+
+        println!("Puzzles: puzzle {} ({:.0}), {} to move", number, rating, side);
+        print_board(&shared_data.board);
+
+        state.texts = [format!("Puzzle {}/{} ({:.0}): {} to move, S shows the solution", number, count, rating, side),
+                       state.status_line()];
+        state.face = Some(color);
+    }
+
+    for move_applied in applied_reader.iter(&moves_applied) {
+        // The opponent's replies come from the solution, only the player's moves are checked
+        if state.solved.is_some() || move_applied.ply % 2 == 1 {
+            continue;
+        }
+
+        let puzzle = &state.puzzles[state.current];
+        let last = move_applied.ply + 1 == shared_data.move_history.len();
+        let mate = last && game_status(&shared_data.board, &shared_data.initial_pos, shared_data.current_move)
+            == GameStatus::Checkmate;
+        let expected = puzzle.solution.get(move_applied.ply);
+
+        if mate || (expected == shared_data.move_history.get(move_applied.ply)
+            && move_applied.ply + 1 == puzzle.solution.len()) {
+            state.finish(true, &config, &mut shared_data);
+        } else if expected == shared_data.move_history.get(move_applied.ply) {
+            state.texts[0] = format!("{} is right, keep going", move_applied.san);
+        } else {
+            state.finish(false, &config, &mut shared_data);
+        }
+    }
+
+    // Giving up counts as failing
+    if state.solved.is_none() && keyboard_input.just_pressed(KeyCode::S) {
+        if let GameState::WaitingForSelect | GameState::PieceSelected = shared_data.game_state {
+            state.finish(false, &config, &mut shared_data);
+        }
+    }
+
+    for (mut text, line) in query.iter_mut() {
+        if text.value != state.texts[line.0] {
+            text.value = state.texts[line.0].clone();
+        }
+    }
+}
+
+// Turns the camera to the player's side when a puzzle starts
+pub fn puzzle_camera_system(config: Res<OrbitCameraConfig>, mut state: ResMut<PuzzleState>,
+                            mut orbit_camera: ResMut<OrbitCamera>) {
+    if let Some(color) = state.face.take() {
+        let preset = if let PieceColor::White = color { CameraPreset::WhiteView } else { CameraPreset::BlackView };
+        let pitch = orbit_camera.target_angle.x;
+
+        orbit_camera.go_to(preset, &config);
+        orbit_camera.target_angle.x = pitch;
+    }
+}
+
+#[derive(Default)]
+pub struct PuzzlePlugin {
+    pub config: PuzzleConfig
+}
+
+impl Plugin for PuzzlePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(PuzzleState::default())
+            .add_startup_system(setup.system())
+            .add_system(puzzle_system.system());
+
+        // Without the orbit camera the view stays where it is
+        if app.resources().contains::<OrbitCamera>() {
+            app.add_system(puzzle_camera_system.system());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puzzles_in_san_and_uci() {
+        let puzzle = parse_puzzle("r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - 0 1 | \
+                                   Bc5+ Kxc5 Qb6+ Kd5 Qd6# | 1700").unwrap();
+
+        assert_eq!(puzzle.solution, ["f8c5", "d4c5", "f6b6", "c5d5", "b6d6"]);
+        assert_eq!(puzzle.solution_san, ["Bc5+", "Kxc5", "Qb6+", "Kd5", "Qd6#"]);
+        assert_eq!(puzzle.rating, 1700.);
+
+        let puzzle = parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1|a1a8").unwrap();
+
+        assert_eq!(puzzle.fen, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert_eq!(puzzle.solution_san, ["Ra8#"]);
+        assert_eq!(puzzle.rating, DEFAULT_PUZZLE_RATING);
+    }
+
+    #[test]
+    fn broken_puzzles() {
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").is_err());
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 | Ra8# | 1500 | 3").is_err());
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 | ").is_err());
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 | Ra9#").is_err());
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 | Ra8# | easy").is_err());
+        assert!(parse_puzzle("6k1/5ppp/8/8 w - - 0 1 | Ra8#").is_err());
+        // Ending on the opponent's reply
+        assert!(parse_puzzle("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 | Ra7 h6").is_err());
+    }
+
+    #[test]
+    fn ratings_move_as_in_elo() {
+        let mut stats = PuzzleStats::default();
+
+        stats.record(1500., true);
+        assert_eq!(stats.rating, 1516.);
+
+        stats.record(1516., false);
+        assert_eq!(stats.rating, 1500.);
+
+        // A much harder puzzle gives much more when solved
+        stats.record(1900., true);
+        assert!((stats.rating - 1529.091).abs() < 0.01);
+        assert_eq!((stats.solved, stats.failed), (2, 1));
+    }
+}
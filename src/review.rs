@@ -0,0 +1,398 @@
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+use crate::controller::Controllers;
+use crate::fen::{START_FEN, parse_fen};
+use crate::hud;
+use crate::pgn::{format_pgn_from, move_to_san};
+use crate::rules::{GameEnded, GameState, LogicChessPiece, PieceColor, SharedData,
+                   check_mate, find_uci_move, make_move};
+use crate::search::{DEFAULT_HASH_MB, MATE_SCORE, Searcher};
+
+// Evaluation drops in centipawns from which a move is an inaccuracy, a mistake or a blunder
+const INACCURACY: i32 = 50;
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 300;
+
+// Mates count as this many centipawns, so a missed mate weighs like a lost queen or two rather than everything
+const SCORE_LIMIT: i32 = 2000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Judgement {
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder
+}
+
+impl Judgement {
+    fn from_loss(loss: i32) -> Self {
+        if loss >= BLUNDER {
+            Judgement::Blunder
+        } else if loss >= MISTAKE {
+            Judgement::Mistake
+        } else if loss >= INACCURACY {
+            Judgement::Inaccuracy
+        } else {
+            Judgement::Good
+        }
+    }
+
+    // The numeric annotation glyph of the judgement in PGN
+    pub fn nag(&self) -> Option<&'static str> {
+        match self {
+            Judgement::Good => None,
+            Judgement::Inaccuracy => Some("$6"),
+            Judgement::Mistake => Some("$2"),
+            Judgement::Blunder => Some("$4")
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Judgement::Good => "Good move",
+            Judgement::Inaccuracy => "Inaccuracy",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder"
+        }
+    }
+}
+
+pub struct MoveReview {
+    pub color: PieceColor,
+    pub san: String,
+    // How much worse the position got for the player, in centipawns
+    pub loss: i32,
+    pub judgement: Judgement,
+    // The engine's move, when it's better than the one played
+    pub better: Option<String>,
+    pub accuracy: f32
+}
+
+#[derive(Default)]
+pub struct PlayerSummary {
+    pub accuracy: f32,
+    pub inaccuracies: usize,
+    pub mistakes: usize,
+    pub blunders: usize
+}
+
+fn opposite(color: PieceColor) -> PieceColor {
+    if let PieceColor::White = color { PieceColor::Black } else { PieceColor::White }
+}
+
+// The score of the side to move, a position without moves is mate or stalemate
+fn position_score(searcher: &mut Searcher, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                  color: PieceColor, depth: u32) -> (i32, Option<String>) {
+    match searcher.iterate(board, initial_pos, color, depth, &[], &mut |_| ()) {
+        Some(info) => (info.score, Some(move_to_san(board, initial_pos, &info.pv[0]))),
+        None if check_mate(board, color) => (-MATE_SCORE, None),
+        None => (0, None)
+    }
+}
+
+// Winning chances in percent for a score of the player, as most online reviews estimate them
+fn win_percent(score: i32) -> f32 {
+    50. + 50. * (2. / (1. + (-0.00368208 * score as f32).exp()) - 1.)
+}
+
+// Replays `history` from `start`, a FEN or None for the starting position, and compares every move with the
+// engine's choice. The move played is searched one ply less deep from the position after it, so both moves are seen
+// equally far.
+pub fn review_game(start: Option<&str>, history: &[String], depth: u32) -> Result<Vec<MoveReview>, String> {
+    let depth = depth.max(2);
+    let position = parse_fen(start.unwrap_or(START_FEN)).map_err(|e| format!("bad start position: {}", e))?;
+    let (mut board, mut initial_pos, mut color) = (position.board, position.initial_pos, position.current_move);
+    let mut reviews = Vec::new();
+    // One table for the whole game, neighbouring positions share most of their lines
+    let mut searcher = Searcher::new(DEFAULT_HASH_MB);
+    let (mut score, mut best) = position_score(&mut searcher, &board, &initial_pos, color, depth);
+
+    for uci in history.iter() {
+        let chess_move = find_uci_move(&board, &initial_pos, color, uci)
+            .ok_or(format!("{} doesn't follow from the starting position", uci))?;
+        let san = move_to_san(&board, &initial_pos, &chess_move);
+
+        make_move(&mut board, &mut initial_pos, &chess_move);
+
+        let (played_score, _) = position_score(&mut searcher, &board, &initial_pos, opposite(color), depth - 1);
+        let (reply_score, reply_best) = position_score(&mut searcher, &board, &initial_pos, opposite(color), depth);
+        let before = score.clamp(-SCORE_LIMIT, SCORE_LIMIT);
+        let after = (-played_score).clamp(-SCORE_LIMIT, SCORE_LIMIT);
+        let loss = (before - after).max(0);
+        let judgement = Judgement::from_loss(loss);
+        let accuracy = 103.1668 * (-0.04354 * (win_percent(before) - win_percent(after)).max(0.)).exp() - 3.1669;
+
+        reviews.push(MoveReview {
+            color,
+            better: best.filter(|best| judgement != Judgement::Good && *best != san),
+            san,
+            loss,
+            judgement,
+            accuracy: accuracy.clamp(0., 100.)
+        });
+
+        score = reply_score;
+        best = reply_best;
+        color = opposite(color);
+    }
+
+    Ok(reviews)
+}
+
+// The NAG and comment written after each move, empty for good moves
+pub fn annotations(reviews: &[MoveReview]) -> Vec<String> {
+    reviews.iter()
+        .map(|review| match (review.judgement.nag(), &review.better) {
+            (None, _) => String::new(),
+            (Some(nag), Some(better)) => format!("{} {{{}, {} was better}}", nag, review.judgement.name(), better),
+            (Some(nag), None) => format!("{} {{{}}}", nag, review.judgement.name())
+        })
+        .collect()
+}
+
+pub fn summary(reviews: &[MoveReview], color: PieceColor) -> PlayerSummary {
+    let moves: Vec<&MoveReview> = reviews.iter().filter(|review| review.color == color).collect();
+    let count = |judgement: Judgement| moves.iter().filter(|review| review.judgement == judgement).count();
+
+    let accuracy = moves.iter().map(|review| review.accuracy).sum::<f32>() / moves.len().max(1) as f32;
+
+    PlayerSummary {
+        accuracy,
+        inaccuracies: count(Judgement::Inaccuracy),
+        mistakes: count(Judgement::Mistake),
+        blunders: count(Judgement::Blunder)
+    }
+}
+
+pub fn summary_text(reviews: &[MoveReview], color: PieceColor) -> String {
+    let summary = summary(reviews, color);
+
+    format!("{}: {:.1}% accuracy, {} inaccuracies, {} mistakes, {} blunders",
+            if let PieceColor::White = color { "White" } else { "Black" },
+            summary.accuracy, summary.inaccuracies, summary.mistakes, summary.blunders)
+}
+
+#[derive(Clone)]
+pub struct ReviewConfig {
+    pub depth: u32,
+    // The reviewed game is written here as annotated PGN
+    pub pgn_path: String
+}
+
+impl Default for ReviewConfig {
+    fn default() -> Self {
+        ReviewConfig {
+            depth: 3,
+            pgn_path: "saves/last_game.pgn".to_string()
+        }
+    }
+}
+
+// The PGN tags, the plies before the first move and the result of the finished game, while it's reviewed in the
+// background
+#[derive(Default)]
+pub struct Review {
+    running: Option<(Vec<(&'static str, String)>, usize, String, Mutex<Receiver<Result<Vec<MoveReview>, String>>>)>
+}
+
+pub struct ReviewText(usize);
+
+pub fn setup(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    for i in 0..2 {
+        commands.spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    top: Val::Px(10. + i as f32 * 26.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load(hud::FONT),
+                style: TextStyle {
+                    font_size: 20.,
+                    color: Color::WHITE,
+                    ..Default::default()
+                }
+            },
+            ..Default::default()
+        })
+            .with(ReviewText(i));
+    }
+}
+
+pub fn review_system(config: Res<ReviewConfig>, shared_data: Res<SharedData>, controllers: Res<Controllers>,
+                     tasks: Res<AsyncComputeTaskPool>, mut ended_reader: Local<EventReader<GameEnded>>,
+                     games_ended: Res<Events<GameEnded>>, mut review: ResMut<Review>,
+                     mut query: Query<(&mut Text, &ReviewText)>) {
+    let mut texts: Option<[String; 2]> = None;
+
+    if let Some(game_ended) = ended_reader.iter(&games_ended).last() {
+        let mut tags = vec![
+            ("Event", "rusty_chess game".to_string()),
+            ("Site", "?".to_string()),
+            ("Date", "????.??.??".to_string()),
+            ("Round", "-".to_string()),
+            ("White", controllers.get(PieceColor::White).description()),
+            ("Black", controllers.get(PieceColor::Black).description()),
+            ("Termination", game_ended.reason.clone()),
+            ("Annotator", "rusty_chess".to_string())
+        ];
+
+        for &(color, tag) in [(PieceColor::White, "WhiteHints"), (PieceColor::Black, "BlackHints")].iter() {
+            if shared_data.hints[color as usize] > 0 {
+                tags.push((tag, shared_data.hints[color as usize].to_string()));
+            }
+        }
+
+        // Moves after a set up position are numbered on from its move number
+        let plies_before = match &shared_data.start {
+            Some(fen) => {
+                tags.push(("SetUp", "1".to_string()));
+                tags.push(("FEN", fen.clone()));
+                parse_fen(fen).map_or(0, |position| position.plies_before())
+            },
+            None => 0
+        };
+
+        let (sender, receiver) = channel();
+        let (start, history, depth) = (shared_data.start.clone(), shared_data.move_history.clone(), config.depth);
+
+        tasks.spawn(async move {
+            let _ = sender.send(review_game(start.as_deref(), &history, depth));
+        }).detach();
+
+        review.running = Some((tags, plies_before, game_ended.result.clone(), Mutex::new(receiver)));
+        texts = Some(["Reviewing the game...".to_string(), String::new()]);
+        println!("Review: analysing {} moves", shared_data.move_history.len());
+    }
+
+    let result = match &review.running {
+        Some((_, _, _, receiver)) => receiver.lock().unwrap().try_recv().ok(),
+        None => None
+    };
+
+    if let Some(result) = result {
+        let (tags, plies_before, game_result, _) = review.running.take().unwrap();
+
+        texts = Some(match result {
+            Ok(reviews) => {
+                let moves: Vec<String> = reviews.iter().map(|review| review.san.clone()).collect();
+                let pgn = format_pgn_from(&tags, &moves, &annotations(&reviews), &game_result, plies_before);
+                let written = Path::new(&config.pgn_path).parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(&config.pgn_path, pgn));
+
+                match written {
+                    Ok(()) => println!("Review: annotated game written to {}", config.pgn_path),
+                    Err(e) => println!("Review: can't write {}: {}", config.pgn_path, e)
+                }
+
+                let texts = [summary_text(&reviews, PieceColor::White), summary_text(&reviews, PieceColor::Black)];
+
+                println!("Review: {}", texts[0]);
+                println!("Review: {}", texts[1]);
+                texts
+            },
+            Err(e) => {
+                println!("Review: {}", e);
+                [format!("No review: {}", e), String::new()]
+            }
+        });
+    }
+
+    // The summary goes away once a new game is under way
+    if let GameState::GameOver = shared_data.game_state {} else if review.running.is_none() {
+        texts = Some([String::new(), String::new()]);
+    }
+
+    if let Some(texts) = texts {
+        for (mut text, line) in query.iter_mut() {
+            if text.value != texts[line.0] {
+                text.value = texts[line.0].clone();
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ReviewPlugin {
+    pub config: ReviewConfig
+}
+
+impl Plugin for ReviewPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(Review::default())
+            .add_startup_system(setup.system())
+            .add_system(review_system.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(moves: &[&str]) -> Vec<String> {
+        moves.iter().map(|uci| uci.to_string()).collect()
+    }
+
+    #[test]
+    fn judgements() {
+        assert_eq!(Judgement::from_loss(0), Judgement::Good);
+        assert_eq!(Judgement::from_loss(49), Judgement::Good);
+        assert_eq!(Judgement::from_loss(50), Judgement::Inaccuracy);
+        assert_eq!(Judgement::from_loss(99), Judgement::Inaccuracy);
+        assert_eq!(Judgement::from_loss(100), Judgement::Mistake);
+        assert_eq!(Judgement::from_loss(299), Judgement::Mistake);
+        assert_eq!(Judgement::from_loss(300), Judgement::Blunder);
+        assert_eq!(Judgement::from_loss(2 * SCORE_LIMIT), Judgement::Blunder);
+    }
+
+    #[test]
+    fn winning_chances() {
+        assert_eq!(win_percent(0), 50.);
+        assert!(win_percent(300) > 75. && win_percent(300) < 76.);
+        assert!((win_percent(300) + win_percent(-300) - 100.).abs() < 0.001);
+    }
+
+    #[test]
+    fn a_missed_mate_is_a_blunder() {
+        // 3... Nf6?? allows 4. Qxf7#
+        let reviews = review_game(None, &history(&["e2e4", "e7e5", "f1c4", "b8c6", "d1h5", "g8f6", "h5f7"]), 3)
+            .unwrap();
+        let nf6 = &reviews[5];
+
+        assert_eq!(reviews.len(), 7);
+        assert_eq!(nf6.san, "Nf6");
+        assert!(matches!(nf6.color, PieceColor::Black));
+        assert_eq!(nf6.judgement, Judgement::Blunder);
+        assert!(nf6.better.is_some());
+        assert_eq!(reviews[6].san, "Qxf7#");
+        assert_eq!(reviews[6].judgement, Judgement::Good);
+
+        let annotations = annotations(&reviews);
+
+        assert!(annotations[5].starts_with("$4 {Blunder, "));
+        assert!(annotations[6].is_empty());
+        assert_eq!(summary(&reviews, PieceColor::Black).blunders, 1);
+        assert_eq!(summary(&reviews, PieceColor::White).blunders, 0);
+    }
+
+    #[test]
+    fn games_from_a_position() {
+        let reviews = review_game(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &history(&["a1a8"]), 2).unwrap();
+
+        assert_eq!(reviews[0].san, "Ra8#");
+        assert_eq!(reviews[0].judgement, Judgement::Good);
+        assert!(review_game(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &history(&["e2e4"]), 2).is_err());
+    }
+}
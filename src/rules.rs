@@ -0,0 +1,1047 @@
+use bevy::prelude::*;
+use std::cmp;
+
+use crate::pgn;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PieceColor {
+    White,
+    Black
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PieceType {
+    King,
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+    Pawn
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoardPosition {
+    pub x: u8,
+    pub y: u8
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MoveKind {
+    Quiet,
+    Capture,
+    Castle
+}
+
+#[derive(Copy, Clone)]
+pub struct MoveRequested {
+    pub from: BoardPosition,
+    pub to: BoardPosition,
+    pub promotion: Option<PieceType>
+}
+
+pub struct MoveApplied {
+    pub ply: usize,
+    pub color: PieceColor,
+    pub san: String,
+    pub from: BoardPosition,
+    pub to: BoardPosition,
+    pub kind: MoveKind,
+    pub promotion: Option<PieceType>
+}
+
+pub struct CheckGiven {
+    pub color: PieceColor
+}
+
+pub struct GameEnded {
+    pub result: String,
+    pub reason: String
+}
+
+pub struct PromotionChosen {
+    pub piece_type: PieceType
+}
+
+// The pawn stays where it was and the same player moves again
+pub struct PromotionCancelled;
+
+// The editor was left for a new game from its position, `analyse` asks for the engine to look at it
+pub struct PositionEdited {
+    pub analyse: bool
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct ChessMove {
+    pub from: BoardPosition,
+    pub to: BoardPosition,
+    pub kind: MoveKind,
+    pub promotion: Option<PieceType>
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate,
+    Stalemate
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct LogicChessPiece {
+    pub piece_color: PieceColor,
+    pub piece_type: PieceType,
+}
+
+pub struct SharedData {
+    pub game_state: GameState,
+    pub cursor_board_pos: BoardPosition,
+    pub current_move: PieceColor,
+    pub board: Vec<Vec<Option<LogicChessPiece>>>,
+    pub initial_pos: Vec<Vec<bool>>,
+    pub move_history: Vec<String>,
+    // The FEN the moves in `move_history` start from, None for the starting position
+    pub start: Option<String>,
+    pub local_color: Option<PieceColor>,
+    pub pending_promotion: Option<ChessMove>,
+    // Hints asked for by White and by Black
+    pub hints: [u32; 2]
+}
+
+impl Default for SharedData {
+    fn default() -> Self {
+        SharedData {
+            game_state: GameState::WaitingForSelect,
+            cursor_board_pos: BoardPosition { x: 0, y: 0 },
+            current_move: PieceColor::White,
+            board: initial_board(),
+            initial_pos: vec![vec![true; 8]; 8],
+            move_history: Vec::new(),
+            start: None,
+            local_color: None,
+            pending_promotion: None,
+            hints: [0, 0]
+        }
+    }
+}
+
+pub enum GameState {
+    WaitingForSelect,
+    PieceSelected,
+    PieceMoving,
+    SpawnPromotionSelector(BoardPosition, PieceColor),
+    PawnPromoting(BoardPosition, PieceColor),
+    Editing,
+    Connecting,
+    // Waiting for the player to say whether the saved game is resumed
+    Resuming,
+    GameOver
+}
+
+pub fn move_system(
+    mut request_reader: Local<EventReader<MoveRequested>>, move_requests: Res<Events<MoveRequested>>,
+    mut promotion_reader: Local<EventReader<PromotionChosen>>, promotions: Res<Events<PromotionChosen>>,
+    mut cancel_reader: Local<EventReader<PromotionCancelled>>, cancels: Res<Events<PromotionCancelled>>,
+    mut moves_applied: ResMut<Events<MoveApplied>>, mut checks_given: ResMut<Events<CheckGiven>>,
+    mut games_ended: ResMut<Events<GameEnded>>, mut shared_data: ResMut<SharedData>) {
+
+    let mut requests: Vec<(BoardPosition, BoardPosition, Option<PieceType>)> = request_reader
+        .iter(&move_requests)
+        .map(|request| (request.from, request.to, request.promotion))
+        .collect();
+
+    for promotion in promotion_reader.iter(&promotions) {
+        if let Some(chess_move) = shared_data.pending_promotion.take() {
+            requests.push((chess_move.from, chess_move.to, Some(promotion.piece_type)));
+            shared_data.game_state = GameState::WaitingForSelect;
+        }
+    }
+
+    for _ in cancel_reader.iter(&cancels) {
+        if shared_data.pending_promotion.take().is_some() {
+            shared_data.game_state = GameState::WaitingForSelect;
+        }
+    }
+
+    for (request_from, request_to, request_promotion) in requests {
+        if let GameState::WaitingForSelect | GameState::PieceSelected = shared_data.game_state {} else {
+            continue;
+        }
+
+        let candidates = matching_moves(&shared_data.board, &shared_data.initial_pos,
+                                        shared_data.current_move, request_from, request_to);
+
+        let chess_move = match (candidates.first(), request_promotion) {
+            (None, _) => continue,
+            (Some(chess_move), None) if chess_move.promotion.is_some() => {
+                shared_data.pending_promotion = Some(*chess_move);
+                shared_data.game_state = GameState::SpawnPromotionSelector(chess_move.to,
+                                                                           shared_data.current_move);
+                continue;
+            },
+            (Some(_), promotion) => {
+                match candidates.iter().find(|chess_move| chess_move.promotion == promotion) {
+                    Some(chess_move) => chess_move,
+                    None => continue
+                }
+            }
+        };
+
+        let color = shared_data.current_move;
+        let san = pgn::move_to_san(&shared_data.board, &shared_data.initial_pos, chess_move);
+
+        let SharedData { board, initial_pos, .. } = &mut *shared_data;
+        make_move(board, initial_pos, chess_move);
+
+        shared_data.move_history.push(chess_move_to_uci(chess_move));
+        shared_data.current_move = match color {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White
+        };
+        shared_data.game_state = GameState::WaitingForSelect;
+
+        moves_applied.send(MoveApplied {
+            ply: shared_data.move_history.len() - 1,
+            color,
+            san,
+            from: chess_move.from,
+            to: move_destination(chess_move),
+            kind: chess_move.kind,
+            promotion: chess_move.promotion
+        });
+
+        if check_mate(&shared_data.board, shared_data.current_move) {
+            checks_given.send(CheckGiven { color: shared_data.current_move });
+        }
+
+        let status = game_status(&shared_data.board, &shared_data.initial_pos, shared_data.current_move);
+
+        if status != GameStatus::Ongoing {
+            games_ended.send(GameEnded {
+                result: pgn::result_string(status, shared_data.current_move).to_string(),
+                reason: if status == GameStatus::Checkmate { "checkmate" } else { "stalemate" }.to_string()
+            });
+        }
+    }
+}
+
+pub fn game_end_system(mut reader: Local<EventReader<GameEnded>>, games_ended: Res<Events<GameEnded>>,
+                   mut shared_data: ResMut<SharedData>) {
+    for game_ended in reader.iter(&games_ended) {
+        if let GameState::GameOver = shared_data.game_state {
+            continue;
+        }
+
+        println!("Game over: {} ({})", game_ended.result, game_ended.reason);
+
+        if shared_data.hints != [0, 0] {
+            println!("Hints: White {}, Black {}", shared_data.hints[0], shared_data.hints[1]);
+        }
+
+        shared_data.game_state = GameState::GameOver;
+    }
+}
+
+pub fn move_log_system(mut move_reader: Local<EventReader<MoveApplied>>, moves_applied: Res<Events<MoveApplied>>,
+                   mut check_reader: Local<EventReader<CheckGiven>>, checks_given: Res<Events<CheckGiven>>,
+                   shared_data: Res<SharedData>) {
+    let mut logged = false;
+
+    for move_applied in move_reader.iter(&moves_applied) {
+        let number = if let PieceColor::White = move_applied.color {
+            format!("{}.", move_applied.ply / 2 + 1)
+        } else {
+            format!("{}...", move_applied.ply / 2 + 1)
+        };
+
+        println!("{} {}", number, move_applied.san);
+        logged = true;
+    }
+
+    for check_given in check_reader.iter(&checks_given) {
+        match check_given.color {
+            PieceColor::White => println!("White is in check"),
+            PieceColor::Black => println!("Black is in check")
+        }
+    }
+
+    if logged {
+        print_board(&shared_data.board);
+    }
+}
+
+// Castling rights are stored as [white O-O, white O-O-O, black O-O, black O-O-O]
+pub fn castling_squares(index: usize) -> (BoardPosition, BoardPosition) {
+    let y = if index < 2 { 0 } else { 7 };
+    let rook_x = if index.is_multiple_of(2) { 7 } else { 0 };
+
+    (BoardPosition { x: 4, y }, BoardPosition { x: rook_x, y })
+}
+
+pub fn castling_possible(board: &[Vec<Option<LogicChessPiece>>], index: usize) -> bool {
+    let color = if index < 2 { PieceColor::White } else { PieceColor::Black };
+    let (king_pos, rook_pos) = castling_squares(index);
+
+    let is_piece = |pos: BoardPosition, piece_type: PieceType| {
+        match board[pos.y as usize][pos.x as usize] {
+            Some(piece) => piece.piece_color == color && piece.piece_type == piece_type,
+            None => false
+        }
+    };
+
+    is_piece(king_pos, PieceType::King) && is_piece(rook_pos, PieceType::Rook)
+}
+
+// The castling rights left in a position, a right needs the king and the rook still unmoved on their squares
+pub fn castling_rights(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>]) -> [bool; 4] {
+    let mut rights = [false; 4];
+
+    for (i, right) in rights.iter_mut().enumerate() {
+        let (king_pos, rook_pos) = castling_squares(i);
+
+        *right = castling_possible(board, i)
+            && initial_pos[king_pos.y as usize][king_pos.x as usize]
+            && initial_pos[rook_pos.y as usize][rook_pos.x as usize];
+    }
+
+    rights
+}
+
+// Pawns on their first rank may still make a double step, kings and rooks count as unmoved only for castling
+pub fn unmoved_pieces(board: &[Vec<Option<LogicChessPiece>>], castling_rights: &[bool; 4]) -> Vec<Vec<bool>> {
+    let mut initial_pos = vec![vec![false; 8]; 8];
+
+    for (i, row) in board.iter().enumerate() {
+        for (j, field) in row.iter().enumerate() {
+            if let Some(piece) = field {
+                initial_pos[i][j] = match (piece.piece_type, piece.piece_color) {
+                    (PieceType::Pawn, PieceColor::White) => i == 1,
+                    (PieceType::Pawn, PieceColor::Black) => i == 6,
+                    _ => false
+                };
+            }
+        }
+    }
+
+    for (i, right) in castling_rights.iter().enumerate() {
+        if *right {
+            let (king_pos, rook_pos) = castling_squares(i);
+
+            initial_pos[king_pos.y as usize][king_pos.x as usize] = true;
+            initial_pos[rook_pos.y as usize][rook_pos.x as usize] = true;
+        }
+    }
+
+    initial_pos
+}
+
+// Neither side can mate: only kings, a single minor piece, or bishops that all stand on squares of one colour
+pub fn insufficient_material(board: &[Vec<Option<LogicChessPiece>>]) -> bool {
+    let mut minors = Vec::new();
+
+    for (y, row) in board.iter().enumerate() {
+        for (x, field) in row.iter().enumerate() {
+            match field.map(|piece| piece.piece_type) {
+                Some(PieceType::King) | None => (),
+                Some(PieceType::Bishop) => minors.push(Some((x + y) % 2)),
+                Some(PieceType::Knight) => minors.push(None),
+                Some(_) => return false
+            }
+        }
+    }
+
+    minors.len() <= 1 || minors.iter().all(|square| square.is_some() && *square == minors[0])
+}
+
+pub fn validate_position(board: &[Vec<Option<LogicChessPiece>>], current_move: PieceColor,
+                     castling_rights: &[bool; 4]) -> Result<(), String> {
+    let mut white_kings = 0;
+    let mut black_kings = 0;
+
+    for (i, row) in board.iter().enumerate() {
+        for piece in row.iter().flatten() {
+            match (piece.piece_type, piece.piece_color) {
+                (PieceType::King, PieceColor::White) => white_kings += 1,
+                (PieceType::King, PieceColor::Black) => black_kings += 1,
+                (PieceType::Pawn, _) if i == 0 || i == 7 => {
+                    return Err("Pawns can't be placed on the first or the last rank".to_string());
+                },
+                _ => ()
+            }
+        }
+    }
+
+    if white_kings != 1 || black_kings != 1 {
+        return Err(format!("Each side needs exactly one king (white: {}, black: {})",
+                           white_kings, black_kings));
+    }
+
+    let waiting_color = match current_move {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White
+    };
+
+    if check_mate(board, waiting_color) {
+        return Err("The side not to move can't be in check".to_string());
+    }
+
+    for (i, right) in castling_rights.iter().enumerate() {
+        if *right && !castling_possible(board, i) {
+            return Err("Castling rights don't match the king and rook placement".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_move(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+              from: BoardPosition, to: BoardPosition) -> Option<MoveKind> {
+    let piece = board[from.y as usize][from.x as usize]?;
+    let dest_field = board[to.y as usize][to.x as usize];
+    let move_vec = Vec2 { x: to.x as f32 - from.x as f32,
+        y: to.y as f32 - from.y as f32 };
+
+    let move_kind = match dest_field {
+        None => {
+            let possible = check_move_pattern(piece.piece_type, piece.piece_color, move_vec,
+                                              initial_pos[from.y as usize][from.x as usize])
+                && (piece.piece_type == PieceType::Knight || !check_move_blocked(board, (from, to)));
+
+            if !possible {
+                return None;
+            }
+
+            MoveKind::Quiet
+        },
+        Some(dest_piece) => {
+            if dest_piece.piece_color == piece.piece_color {
+                if piece.piece_type == PieceType::King && dest_piece.piece_type == PieceType::Rook
+                && check_castling(board, initial_pos, (from, to)) {
+                    return Some(MoveKind::Castle);
+                }
+
+                return None;
+            }
+
+            let possible = check_capture_pattern(piece.piece_type, piece.piece_color, (from, to))
+                && (piece.piece_type == PieceType::Knight || !check_move_blocked(board, (from, to)));
+
+            if !possible {
+                return None;
+            }
+
+            MoveKind::Capture
+        }
+    };
+
+    let mut board_copy = board.to_vec();
+    board_copy[from.y as usize][from.x as usize] = None;
+    board_copy[to.y as usize][to.x as usize] = Some(piece);
+
+    if check_mate(&board_copy, piece.piece_color) {
+        return None;
+    }
+
+    Some(move_kind)
+}
+
+pub fn initial_board() -> Vec<Vec<Option<LogicChessPiece>>> {
+    let back_rank = [ PieceType::Rook, PieceType::Knight, PieceType::Bishop, PieceType::Queen,
+                      PieceType::King, PieceType::Bishop, PieceType::Knight, PieceType::Rook ];
+    let mut board = vec![vec![None; 8]; 8];
+
+    for i in 0..8 {
+        board[0][i] = Some(LogicChessPiece { piece_color: PieceColor::White, piece_type: back_rank[i] });
+        board[1][i] = Some(LogicChessPiece { piece_color: PieceColor::White, piece_type: PieceType::Pawn });
+        board[6][i] = Some(LogicChessPiece { piece_color: PieceColor::Black, piece_type: PieceType::Pawn });
+        board[7][i] = Some(LogicChessPiece { piece_color: PieceColor::Black, piece_type: back_rank[i] });
+    }
+
+    board
+}
+
+pub fn legal_moves(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+               color: PieceColor) -> Vec<ChessMove> {
+    let mut moves = Vec::new();
+
+    for from_y in 0..8u8 {
+        for from_x in 0..8u8 {
+            let piece = match board[from_y as usize][from_x as usize] {
+                Some(piece) if piece.piece_color == color => piece,
+                _ => continue
+            };
+            let from = BoardPosition { x: from_x, y: from_y };
+
+            for to_y in 0..8u8 {
+                for to_x in 0..8u8 {
+                    let to = BoardPosition { x: to_x, y: to_y };
+                    let kind = match check_move(board, initial_pos, from, to) {
+                        Some(kind) => kind,
+                        None => continue
+                    };
+
+                    if piece.piece_type == PieceType::Pawn && (to_y == 0 || to_y == 7) {
+                        for &promotion in [PieceType::Queen, PieceType::Rook,
+                                           PieceType::Bishop, PieceType::Knight].iter() {
+                            moves.push(ChessMove { from, to, kind, promotion: Some(promotion) });
+                        }
+                    } else {
+                        moves.push(ChessMove { from, to, kind, promotion: None });
+                    }
+                }
+            }
+        }
+    }
+
+    moves
+}
+
+// Castling moves can be asked for with either the king's landing square or the rook's square
+pub fn matching_moves(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                      color: PieceColor, from: BoardPosition, to: BoardPosition) -> Vec<ChessMove> {
+    legal_moves(board, initial_pos, color).into_iter()
+        .filter(|chess_move| chess_move.from == from
+            && (chess_move.to == to || move_destination(chess_move) == to))
+        .collect()
+}
+
+// Castling is stored as the king moving onto its rook, this gives the square the king actually lands on
+pub fn move_destination(chess_move: &ChessMove) -> BoardPosition {
+    match chess_move.kind {
+        MoveKind::Castle => BoardPosition { x: if chess_move.to.x == 0 { 2 } else { 6 }, y: chess_move.to.y },
+        _ => chess_move.to
+    }
+}
+
+pub fn make_move(board: &mut [Vec<Option<LogicChessPiece>>], initial_pos: &mut [Vec<bool>],
+             chess_move: &ChessMove) {
+    let (from, to) = (chess_move.from, chess_move.to);
+    let mut piece = match board[from.y as usize][from.x as usize] {
+        Some(piece) => piece,
+        None => return
+    };
+    let destination = move_destination(chess_move);
+
+    board[from.y as usize][from.x as usize] = None;
+    initial_pos[from.y as usize][from.x as usize] = false;
+
+    if chess_move.kind == MoveKind::Castle {
+        let rook_x = if to.x == 0 { 3 } else { 5 };
+
+        board[to.y as usize][to.x as usize] = None;
+        initial_pos[to.y as usize][to.x as usize] = false;
+        board[to.y as usize][rook_x] = Some(LogicChessPiece {
+            piece_color: piece.piece_color,
+            piece_type: PieceType::Rook
+        });
+    }
+
+    if let Some(piece_type) = chess_move.promotion {
+        piece.piece_type = piece_type;
+    }
+
+    board[destination.y as usize][destination.x as usize] = Some(piece);
+    initial_pos[destination.y as usize][destination.x as usize] = false;
+}
+
+pub fn game_status(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+               color: PieceColor) -> GameStatus {
+    if !legal_moves(board, initial_pos, color).is_empty() {
+        return GameStatus::Ongoing;
+    }
+
+    if check_mate(board, color) {
+        GameStatus::Checkmate
+    } else {
+        GameStatus::Stalemate
+    }
+}
+
+pub fn chess_move_to_uci(chess_move: &ChessMove) -> String {
+    move_to_uci(chess_move.from, move_destination(chess_move), chess_move.promotion)
+}
+
+pub fn find_uci_move(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                 color: PieceColor, uci: &str) -> Option<ChessMove> {
+    legal_moves(board, initial_pos, color).into_iter()
+        .find(|chess_move| chess_move_to_uci(chess_move) == uci)
+}
+
+pub fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::King => 'K',
+        PieceType::Queen => 'Q',
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Knight => 'N',
+        PieceType::Pawn => 'P'
+    }
+}
+
+pub fn position_to_string(position: BoardPosition) -> String {
+    format!("{}{}", (b'a' + position.x) as char, position.y + 1)
+}
+
+pub fn parse_position(text: &str) -> Option<BoardPosition> {
+    let bytes = text.as_bytes();
+
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+
+    Some(BoardPosition { x: bytes[0] - b'a', y: bytes[1] - b'1' })
+}
+
+pub fn move_to_uci(from: BoardPosition, to: BoardPosition, promotion: Option<PieceType>) -> String {
+    let mut uci = position_to_string(from) + &position_to_string(to);
+
+    if let Some(piece_type) = promotion {
+        uci.push(piece_letter(piece_type).to_ascii_lowercase());
+    }
+
+    uci
+}
+
+pub fn parse_uci(uci: &str) -> Option<(BoardPosition, BoardPosition, Option<PieceType>)> {
+    if !uci.is_ascii() || (uci.len() != 4 && uci.len() != 5) {
+        return None;
+    }
+
+    let from = parse_position(&uci[0..2])?;
+    let to = parse_position(&uci[2..4])?;
+    let promotion = match uci.get(4..) {
+        None | Some("") => None,
+        Some("q") => Some(PieceType::Queen),
+        Some("r") => Some(PieceType::Rook),
+        Some("b") => Some(PieceType::Bishop),
+        Some("n") => Some(PieceType::Knight),
+        Some(_) => return None
+    };
+
+    Some((from, to, promotion))
+}
+
+pub fn check_mate(board: &[Vec<Option<LogicChessPiece>>], color: PieceColor) -> bool {
+    let mut king_pos = BoardPosition {x: 0, y: 0};
+    let mut possible_threats : Vec<(PieceType, BoardPosition)> = Vec::new();
+
+    let enemy_color = match color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White
+    };
+
+    for (i, row) in board.iter().enumerate() {
+        for (j, field) in row.iter().enumerate() {
+            match field {
+                None => {}
+                Some(piece) => {
+                    if let PieceType::King = piece.piece_type {
+                        if color == piece.piece_color {
+                            king_pos.x = j as u8;
+                            king_pos.y = i as u8;
+                        }
+                    }
+
+                    if let PieceType::Knight = piece.piece_type {
+                        if piece.piece_color == enemy_color {
+                            possible_threats.push((piece.piece_type,
+                                                   BoardPosition { x: j as u8, y: i as u8 }));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Check left
+    if king_pos.x > 0 {
+        for i in (0..king_pos.x).rev() {
+            match board[king_pos.y as usize][i as usize] {
+                None => {}
+                Some(piece) => {
+                    if color != piece.piece_color {
+                        possible_threats.push((piece.piece_type, BoardPosition { x: i, y: king_pos.y }));
+                    }
+
+                    break;
+                }
+            }
+        }
+    }
+
+    // Check right
+    for i in (king_pos.x + 1)..8 {
+        match board[king_pos.y as usize][i as usize] {
+            None => {}
+            Some(piece) => {
+                if enemy_color == piece.piece_color {
+                    possible_threats.push((piece.piece_type, BoardPosition {x: i, y: king_pos.y}));
+                }
+
+                break;
+            }
+        }
+    }
+
+    // Check up
+    for i in (king_pos.y + 1)..8 {
+        match board[i as usize][king_pos.x as usize] {
+            None => {}
+            Some(piece) => {
+                if enemy_color == piece.piece_color {
+                    possible_threats.push((piece.piece_type, BoardPosition {x: king_pos.x, y: i}));
+                }
+
+                break;
+            }
+        }
+    }
+
+    // Check down
+    if king_pos.y > 0 {
+        for i in (0..king_pos.y).rev() {
+            match board[i as usize][king_pos.x as usize] {
+                None => {}
+                Some(piece) => {
+                    if enemy_color == piece.piece_color {
+                        possible_threats.push((piece.piece_type, BoardPosition { x: king_pos.x, y: i }));
+                    }
+
+                    break;
+                }
+            }
+        }
+    }
+
+    // Check upper-left
+    if king_pos.x > 0 {
+        let limit = cmp::min(king_pos.x + 1, 8 - king_pos.y);
+
+        for i in 1..limit {
+            match board[(king_pos.y + i) as usize][(king_pos.x - i) as usize] {
+                None => {}
+                Some(piece) => {
+                    if enemy_color == piece.piece_color {
+                        possible_threats.push((piece.piece_type, BoardPosition { x: king_pos.x - i, y: king_pos.y + i }));
+                    }
+
+                    break;
+                }
+            }
+        }
+    }
+
+    // Check upper-right
+    let limit = cmp::min(8 - king_pos.x, 8 - king_pos.y);
+
+    for i in 1..limit {
+        match board[(king_pos.y + i) as usize][(king_pos.x + i) as usize] {
+            None => {}
+            Some(piece) => {
+                if enemy_color == piece.piece_color {
+                    possible_threats.push((piece.piece_type, BoardPosition { x: king_pos.x + i, y: king_pos.y + i }));
+                }
+
+                break;
+            }
+        }
+    }
+
+    // Check lower-right
+    if king_pos.y > 0 {
+        let limit = cmp::min(8 - king_pos.x, king_pos.y + 1);
+
+        for i in 1..limit {
+            match board[(king_pos.y - i) as usize][(king_pos.x + i) as usize] {
+                None => {}
+                Some(piece) => {
+                    if enemy_color == piece.piece_color {
+                        possible_threats.push((piece.piece_type, BoardPosition { x: king_pos.x + i, y: king_pos.y - i }));
+                    }
+
+                    break;
+                }
+            }
+        }
+    }
+
+    // Check lower-left
+    if king_pos.x > 0 && king_pos.y > 0 {
+        let limit = cmp::min(king_pos.x + 1, king_pos.y + 1);
+
+        for i in 1..limit {
+            match board[(king_pos.y - i) as usize][(king_pos.x - i) as usize] {
+                None => {}
+                Some(piece) => {
+                    if enemy_color == piece.piece_color {
+                        possible_threats.push((piece.piece_type, BoardPosition { x: king_pos.x - i, y: king_pos.y - i }));
+                    }
+
+                    break;
+                }
+            }
+        }
+    }
+
+    for i in possible_threats.iter() {
+        let possible = check_capture_pattern(i.0, enemy_color, (i.1, king_pos));
+
+        if possible {
+            return true;
+        }
+    }
+
+    false
+}
+
+pub fn check_move_blocked(board: &[Vec<Option<LogicChessPiece>>],
+                      piece_move: (BoardPosition, BoardPosition)) -> bool{
+
+    let (from, to) = piece_move;
+    let diff = Vec2 { x: to.x as f32 - from.x as f32,
+        y: to.y as f32 - from.y as f32 };
+
+    if diff.x == 0. && diff.y == 0. {
+        return true;
+    }
+
+    let field = board[piece_move.0.y as usize][piece_move.0.x as usize];
+
+
+    if field.is_none() {
+        return true;
+    }
+
+    let dir = if diff.x == 0. {
+        Vec2 { x: 0., y: diff.y / diff.y.abs() }
+    } else if diff.y == 0. {
+        Vec2 { x: diff.x / diff.x.abs(), y: 0. }
+    } else {
+        Vec2 { x: diff.x / diff.x.abs(), y: diff.y / diff.y.abs() }
+    };
+
+    let mut checked_pos = Vec2 { x: piece_move.0.x as f32 + dir.x, y: piece_move.0.y as f32 + dir.y };
+
+    while !(checked_pos.x == piece_move.1.x as f32
+        && checked_pos.y == piece_move.1.y as f32) {
+
+        let curr_field = board[checked_pos.y as usize][checked_pos.x as usize];
+
+        if curr_field.is_some() {
+            return true;
+        }
+
+        checked_pos.x += dir.x;
+        checked_pos.y += dir.y;
+    }
+
+    false
+}
+
+pub fn check_move_pattern(piece_type: PieceType, piece_color: PieceColor,
+                      move_vec: Vec2, initial_pos: bool) -> bool {
+    if move_vec.x == 0. && move_vec.y == 0. {
+        return false;
+    }
+
+    let move_vec = if let PieceColor::Black = piece_color {
+        Vec2 { x: move_vec.x, y: -move_vec.y }
+    } else {
+        move_vec
+    };
+
+    match piece_type {
+        PieceType::King => {
+            (move_vec.x.abs() == 0. || move_vec.x.abs() == 1.)
+                && (move_vec.y.abs() == 0. || move_vec.y.abs() == 1.)
+        }
+        PieceType::Queen => {
+            move_vec.x.abs() == 0. || move_vec.y.abs() == 0.
+                || move_vec.x.abs() == move_vec.y.abs()
+        }
+        PieceType::Rook => {
+            move_vec.x.abs() == 0. || move_vec.y.abs() == 0.
+        }
+        PieceType::Bishop => {
+            move_vec.x.abs() == move_vec.y.abs()
+        }
+        PieceType::Knight => {
+            move_vec.x.abs() == 1. && move_vec.y.abs() == 2.
+                || move_vec.x.abs() == 2. && move_vec.y.abs() == 1.
+        }
+        PieceType::Pawn => {
+            if initial_pos {
+                move_vec.x == 0. && (move_vec.y == 1. || move_vec.y == 2.)
+            } else {
+                move_vec.x == 0. && move_vec.y == 1.
+            }
+        }
+    }
+}
+
+pub fn check_capture_pattern(piece_type: PieceType, piece_color: PieceColor,
+                         piece_move: (BoardPosition, BoardPosition)) -> bool {
+    let (from, to) = piece_move;
+
+    let diff = Vec2 { x: to.x as f32 - from.x as f32, y: to.y as f32 - from.y as f32 };
+
+    if let PieceType::Pawn = piece_type {
+        let diff = if let PieceColor::Black = piece_color {
+            Vec2 { x: diff.x, y: -diff.y }
+        } else {
+            diff
+        };
+
+        if diff.x.abs() == 1. && diff.y == 1. {
+            return true;
+        }
+    } else {
+        return check_move_pattern(piece_type, piece_color, diff, false);
+    }
+
+    false
+}
+
+pub fn check_castling(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                  checked_move: (BoardPosition, BoardPosition)) -> bool {
+    let (from, to) = checked_move;
+
+    if !initial_pos[from.y as usize][from.x as usize]
+        || !initial_pos[to.y as usize][to.x as usize] {
+        return false;
+    }
+
+    let piece_color = match board[from.y as usize][from.x as usize] {
+        None => return false,
+        Some(source_piece) => source_piece.piece_color
+    };
+
+    let dir: i8 = if checked_move.0.x > checked_move.1.x {
+        -1
+    } else {
+        1
+    };
+
+    let mut checked_pos = from;
+
+    for _ in 1u8..3 {
+        checked_pos.x = (checked_pos.x as i8 + dir) as u8;
+        let checked_field = board[checked_pos.y as usize][checked_pos.x as usize];
+
+        match checked_field {
+            None => {
+                let mut board_copy = board.to_vec();
+                board_copy[checked_move.0.y as usize][checked_move.0.x as usize] = None;
+                board_copy[checked_pos.y as usize][checked_pos.x as usize] = Some(LogicChessPiece {
+                    piece_color,
+                    piece_type: PieceType::King
+                });
+
+                if check_mate(&board_copy, piece_color) {
+                    return false;
+                }
+            },
+            Some(_) => {
+                return false;
+            }
+        }
+    }
+
+    if checked_move.1.x == 0 && board[checked_move.1.y as usize][1].is_some() {
+        return false;
+    }
+
+    true
+}
+
+pub fn print_board(board: &[Vec<Option<LogicChessPiece>>]) {
+    for i in board.iter().rev() {
+        for j in i.iter() {
+            let symbol = match j {
+                None => " ",
+                Some(piece) => {
+                    if let PieceColor::White = piece.piece_color {
+                        match piece.piece_type {
+                            PieceType::King => "♔",
+                            PieceType::Queen => "♕",
+                            PieceType::Rook => "♖",
+                            PieceType::Bishop => "♗",
+                            PieceType::Knight => "♘",
+                            PieceType::Pawn => "♙"
+                        }
+                    } else {
+                        match piece.piece_type {
+                            PieceType::King => "♚",
+                            PieceType::Queen => "♛",
+                            PieceType::Rook => "♜",
+                            PieceType::Bishop => "♝",
+                            PieceType::Knight => "♞",
+                            PieceType::Pawn => "♟"
+                        }
+                    }
+                }
+            };
+
+            print!("{}", symbol);
+
+        }
+
+        println!();
+    }
+}
+
+pub struct ChessRulesPlugin {
+    pub log_moves: bool
+}
+
+impl Default for ChessRulesPlugin {
+    fn default() -> Self {
+        ChessRulesPlugin { log_moves: true }
+    }
+}
+
+impl Plugin for ChessRulesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(SharedData::default())
+            .add_event::<MoveRequested>()
+            .add_event::<MoveApplied>()
+            .add_event::<CheckGiven>()
+            .add_event::<GameEnded>()
+            .add_event::<PromotionChosen>()
+            .add_event::<PromotionCancelled>()
+            .add_event::<PositionEdited>()
+            .add_system(move_system.system())
+            .add_system(game_end_system.system());
+
+        if self.log_moves {
+            app.add_system(move_log_system.system());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+
+    fn insufficient(fen: &str) -> bool {
+        insufficient_material(&parse_fen(fen).unwrap().board)
+    }
+
+    #[test]
+    fn dead_positions() {
+        assert!(insufficient("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1"));
+        // Bishops on squares of one colour, whoever they belong to
+        assert!(insufficient("2b1k3/8/8/8/8/8/8/4KB2 w - - 0 1"));
+    }
+
+    #[test]
+    fn positions_with_mating_material() {
+        assert!(!insufficient("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1N2K1N1 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"));
+        assert!(!insufficient("1n2k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+    }
+}
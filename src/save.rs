@@ -0,0 +1,354 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::camera::OrbitCamera;
+use crate::controller::{Controllers, EngineOptions, parse_controller};
+use crate::fen::parse_fen;
+use crate::hud;
+use crate::rules::{GameEnded, GameState, LogicChessPiece, MoveApplied, PieceColor, PieceType, SharedData,
+                   piece_letter, print_board, validate_position};
+
+// Goes up whenever the file layout changes, older files are refused rather than misread
+pub const SAVE_VERSION: u32 = 1;
+
+#[derive(Clone)]
+pub struct SaveConfig {
+    // Written after every move and offered for resuming on startup
+    pub autosave_path: String,
+    // Written with Ctrl+S and read with Ctrl+O
+    pub save_path: String,
+    pub autosave: bool,
+    pub offer_resume: bool
+}
+
+impl Default for SaveConfig {
+    fn default() -> Self {
+        SaveConfig {
+            autosave_path: "saves/autosave.ron".to_string(),
+            save_path: "saves/game.ron".to_string(),
+            autosave: true,
+            offer_resume: true
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedGame {
+    pub version: u32,
+    // One line per rank starting with the first, pieces as in FEN and '.' for an empty square
+    pub board: Vec<String>,
+    // The same ranks with 'x' where the piece hasn't moved yet, castling depends on them
+    pub unmoved: Vec<String>,
+    pub current_move: String,
+    // The moves in UCI notation
+    pub move_history: Vec<String>,
+    // The FEN the moves start from, missing for the starting position
+    #[serde(default)]
+    pub start: Option<String>,
+    // Player descriptions as on the command line
+    pub white: String,
+    pub black: String,
+    // Pitch, yaw and distance of the orbit camera
+    pub camera: Option<(f32, f32, f32)>,
+    pub finished: bool,
+    // Hints asked for by White and by Black, missing in saves from before hints
+    #[serde(default)]
+    pub hints: [u32; 2]
+}
+
+fn color_name(color: PieceColor) -> &'static str {
+    if let PieceColor::White = color { "white" } else { "black" }
+}
+
+fn piece_char(piece: &Option<LogicChessPiece>) -> char {
+    match piece {
+        Some(piece) if piece.piece_color == PieceColor::White => piece_letter(piece.piece_type),
+        Some(piece) => piece_letter(piece.piece_type).to_ascii_lowercase(),
+        None => '.'
+    }
+}
+
+fn parse_piece(c: char) -> Result<Option<LogicChessPiece>, String> {
+    let piece_type = match c.to_ascii_uppercase() {
+        '.' => return Ok(None),
+        'K' => PieceType::King,
+        'Q' => PieceType::Queen,
+        'R' => PieceType::Rook,
+        'B' => PieceType::Bishop,
+        'N' => PieceType::Knight,
+        'P' => PieceType::Pawn,
+        _ => return Err(format!("unknown piece: {}", c))
+    };
+    let piece_color = if c.is_ascii_uppercase() { PieceColor::White } else { PieceColor::Black };
+
+    Ok(Some(LogicChessPiece { piece_color, piece_type }))
+}
+
+impl SavedGame {
+    pub fn new(shared_data: &SharedData, controllers: &Controllers, camera: Option<(f32, f32, f32)>,
+               finished: bool) -> Self {
+        SavedGame {
+            version: SAVE_VERSION,
+            board: shared_data.board.iter()
+                .map(|row| row.iter().map(piece_char).collect())
+                .collect(),
+            unmoved: shared_data.initial_pos.iter()
+                .map(|row| row.iter().map(|unmoved| if *unmoved { 'x' } else { '.' }).collect())
+                .collect(),
+            current_move: color_name(shared_data.current_move).to_string(),
+            move_history: shared_data.move_history.clone(),
+            start: shared_data.start.clone(),
+            white: controllers.get(PieceColor::White).description(),
+            black: controllers.get(PieceColor::Black).description(),
+            camera,
+            finished,
+            hints: shared_data.hints
+        }
+    }
+
+    // Checked as a whole before anything is changed, a broken file leaves the game as it is
+    pub fn restore(&self, shared_data: &mut SharedData, controllers: &mut Controllers,
+                   engine_options: &EngineOptions) -> Result<(), String> {
+        if self.version != SAVE_VERSION {
+            return Err(format!("unsupported save version {}, expected {}", self.version, SAVE_VERSION));
+        }
+
+        if self.board.len() != 8 || self.board.iter().any(|row| row.chars().count() != 8) {
+            return Err("the board must have 8 ranks of 8 squares".to_string());
+        }
+
+        if self.unmoved.len() != 8 || self.unmoved.iter().any(|row| row.chars().count() != 8) {
+            return Err("the unmoved pieces must be given for 8 ranks of 8 squares".to_string());
+        }
+
+        let board = self.board.iter()
+            .map(|row| row.chars().map(parse_piece).collect::<Result<Vec<_>, String>>())
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let current_move = match self.current_move.as_str() {
+            "white" => PieceColor::White,
+            "black" => PieceColor::Black,
+            other => return Err(format!("unknown side to move: {}", other))
+        };
+
+        validate_position(&board, current_move, &[false; 4])?;
+
+        if let Some(start) = &self.start {
+            parse_fen(start).map_err(|e| format!("bad start position: {}", e))?;
+        }
+
+        shared_data.board = board;
+        shared_data.initial_pos = self.unmoved.iter()
+            .map(|row| row.chars().map(|c| c == 'x').collect())
+            .collect();
+        shared_data.current_move = current_move;
+        shared_data.move_history = self.move_history.clone();
+        shared_data.start = self.start.clone();
+        shared_data.pending_promotion = None;
+        shared_data.hints = self.hints;
+        shared_data.game_state = if self.finished { GameState::GameOver } else { GameState::WaitingForSelect };
+
+        // A player that can't be brought back, like an engine that's gone, is played with the mouse
+        for &(color, description) in [(PieceColor::White, &self.white), (PieceColor::Black, &self.black)].iter() {
+            match parse_controller(description, engine_options) {
+                Ok(controller) => controllers.set(color, controller),
+                Err(e) => println!("Save: {}, {} plays with the mouse", e, color_name(color))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn save_game(path: &str, game: &SavedGame) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(game, ron::ser::PrettyConfig::new())
+        .map_err(|e| format!("can't write {}: {}", path, e))?;
+
+    if let Some(directory) = Path::new(path).parent() {
+        fs::create_dir_all(directory).map_err(|e| format!("can't create {}: {}", directory.display(), e))?;
+    }
+
+    fs::write(path, text).map_err(|e| format!("can't write {}: {}", path, e))
+}
+
+pub fn load_game(path: &str) -> Result<SavedGame, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+
+    ron::de::from_str(&text).map_err(|e| format!("can't parse {}: {}", path, e))
+}
+
+#[derive(Default)]
+pub struct SaveState {
+    // The unfinished game waiting for the player to resume it
+    pub offered: Option<SavedGame>,
+    pub camera: Option<(f32, f32, f32)>,
+    restore_camera: Option<(f32, f32, f32)>,
+    finished: bool
+}
+
+pub struct ResumePrompt;
+
+pub fn setup(commands: &mut Commands, config: Res<SaveConfig>, asset_server: Res<AssetServer>,
+             mut state: ResMut<SaveState>, mut shared_data: ResMut<SharedData>) {
+    if !config.offer_resume {
+        return;
+    }
+
+    let game = match load_game(&config.autosave_path) {
+        Ok(game) if !game.finished && !game.move_history.is_empty() => game,
+        _ => return
+    };
+
+    println!("Resume the unfinished game from {} ({} moves played)? Y/N",
+             config.autosave_path, game.move_history.len());
+
+    commands.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(10.),
+                top: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text {
+            value: format!("Resume the unfinished game ({} moves played)? Y/N", game.move_history.len()),
+            font: asset_server.load(hud::FONT),
+            style: TextStyle {
+                font_size: 24.,
+                color: Color::WHITE,
+                ..Default::default()
+            }
+        },
+        ..Default::default()
+    })
+        .with(ResumePrompt);
+
+    state.offered = Some(game);
+    shared_data.game_state = GameState::Resuming;
+}
+
+pub fn resume_system(commands: &mut Commands, keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<SaveState>,
+                     mut shared_data: ResMut<SharedData>, mut controllers: ResMut<Controllers>,
+                     engine_options: Res<EngineOptions>, query: Query<Entity, With<ResumePrompt>>) {
+    if state.offered.is_none() {
+        return;
+    }
+
+    let resume = keyboard_input.just_pressed(KeyCode::Y);
+
+    if !resume && !keyboard_input.just_pressed(KeyCode::N) && !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    let game = state.offered.take().unwrap();
+
+    for entity in query.iter() {
+        commands.despawn(entity);
+    }
+
+    shared_data.game_state = GameState::WaitingForSelect;
+
+    if !resume {
+        return;
+    }
+
+    match game.restore(&mut shared_data, &mut controllers, &engine_options) {
+        Ok(()) => {
+            println!("Save: resumed the game, white: {}, black: {}", game.white, game.black);
+            print_board(&shared_data.board);
+            state.restore_camera = game.camera;
+        },
+        Err(e) => println!("Save: {}", e)
+    }
+}
+
+// Autosaves after every move and when the game ends, Ctrl+S saves and Ctrl+O loads
+pub fn save_system(keyboard_input: Res<Input<KeyCode>>, config: Res<SaveConfig>, mut state: ResMut<SaveState>,
+                   mut applied_reader: Local<EventReader<MoveApplied>>, moves_applied: Res<Events<MoveApplied>>,
+                   mut ended_reader: Local<EventReader<GameEnded>>, games_ended: Res<Events<GameEnded>>,
+                   mut shared_data: ResMut<SharedData>, mut controllers: ResMut<Controllers>,
+                   engine_options: Res<EngineOptions>) {
+    let moved = applied_reader.iter(&moves_applied).count() > 0;
+    let ended = ended_reader.iter(&games_ended).count() > 0;
+
+    if ended {
+        state.finished = true;
+    }
+
+    if config.autosave && (moved || ended) {
+        let game = SavedGame::new(&shared_data, &controllers, state.camera, state.finished);
+
+        if let Err(e) = save_game(&config.autosave_path, &game) {
+            println!("Save: {}", e);
+        }
+    }
+
+    let control = keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+
+    if !control {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::S) {
+        let game = SavedGame::new(&shared_data, &controllers, state.camera, state.finished);
+
+        match save_game(&config.save_path, &game) {
+            Ok(()) => println!("Save: game saved to {}", config.save_path),
+            Err(e) => println!("Save: {}", e)
+        }
+    }
+
+    // Only between moves, so no half made move or promotion is lost
+    if keyboard_input.just_pressed(KeyCode::O) {
+        match shared_data.game_state {
+            GameState::WaitingForSelect | GameState::GameOver => (),
+            _ => return
+        }
+
+        match load_game(&config.save_path).and_then(|game| {
+            game.restore(&mut shared_data, &mut controllers, &engine_options).map(|_| game)
+        }) {
+            Ok(game) => {
+                println!("Save: game loaded from {}", config.save_path);
+                print_board(&shared_data.board);
+                state.finished = game.finished;
+                state.restore_camera = game.camera;
+            },
+            Err(e) => println!("Save: {}", e)
+        }
+    }
+}
+
+// Only added along with the orbit camera, saves made without it keep no camera
+pub fn camera_state_system(mut state: ResMut<SaveState>, mut orbit_camera: ResMut<OrbitCamera>) {
+    if let Some((pitch, yaw, distance)) = state.restore_camera.take() {
+        orbit_camera.target_angle = Vec3::new(pitch, yaw, 0.);
+        orbit_camera.target_distance = distance;
+    }
+
+    state.camera = Some((orbit_camera.target_angle.x, orbit_camera.target_angle.y, orbit_camera.target_distance));
+}
+
+#[derive(Default)]
+pub struct SavePlugin {
+    pub config: SaveConfig
+}
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_resource(SaveState::default())
+            .add_startup_system(setup.system())
+            .add_system(resume_system.system())
+            .add_system(save_system.system());
+
+        // OrbitCameraPlugin comes before this one in ChessPlugins
+        if app.resources().contains::<OrbitCamera>() {
+            app.add_system(camera_state_system.system());
+        }
+    }
+}
@@ -0,0 +1,952 @@
+use bevy::tasks::{TaskPool, TaskPoolBuilder};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::clock::Deadlines;
+use crate::rules::{ChessMove, LogicChessPiece, MoveKind, PieceColor, PieceType,
+                   chess_move_to_uci, check_mate, legal_moves, make_move};
+
+pub const MATE_SCORE: i32 = 100_000;
+// Scores beyond this are mates, the distance to the mate is taken off MATE_SCORE
+pub const MATE_BOUND: i32 = MATE_SCORE - 1000;
+pub const DEFAULT_HASH_MB: usize = 16;
+// The deepest a search goes when only the time limits it, the time runs out long before
+pub const MAX_DEPTH: u32 = 32;
+
+const MAX_PLY: usize = 64;
+// Half the width of the window the next depth is first searched with, around the last score
+const ASPIRATION_WINDOW: i32 = 50;
+
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 900,
+        PieceType::Rook => 500,
+        PieceType::Bishop => 330,
+        PieceType::Knight => 320,
+        PieceType::Pawn => 100
+    }
+}
+
+fn opposite(color: PieceColor) -> PieceColor {
+    match color {
+        PieceColor::White => PieceColor::Black,
+        PieceColor::Black => PieceColor::White
+    }
+}
+
+// Material plus small bonuses for central pieces and advanced pawns, from the point of view of `color`
+pub fn evaluate(board: &[Vec<Option<LogicChessPiece>>], color: PieceColor) -> i32 {
+    let mut score = 0;
+
+    for (y, row) in board.iter().enumerate() {
+        for (x, field) in row.iter().enumerate() {
+            let piece = match field {
+                Some(piece) => piece,
+                None => continue
+            };
+
+            let centre_distance = (2 * x as i32 - 7).abs() + (2 * y as i32 - 7).abs();
+            let advance = if let PieceColor::White = piece.piece_color { y as i32 - 1 } else { 6 - y as i32 };
+            let bonus = match piece.piece_type {
+                PieceType::Pawn => advance * 5 + if centre_distance <= 4 { 10 } else { 0 },
+                PieceType::Knight | PieceType::Bishop => 14 - centre_distance,
+                PieceType::Queen => (14 - centre_distance) / 2,
+                _ => 0
+            };
+            let value = piece_value(piece.piece_type) + bonus;
+
+            score += if piece.piece_color == color { value } else { -value };
+        }
+    }
+
+    score
+}
+
+
+pub struct SearchInfo {
+    pub depth: u32,
+    // From the point of view of the side to move
+    pub score: i32,
+    pub nodes: u64,
+    pub nps: u64,
+    pub pv: Vec<ChessMove>
+}
+
+impl SearchInfo {
+    // The line a UCI engine prints for it
+    pub fn uci(&self) -> String {
+        let score = if self.score.abs() > MATE_BOUND {
+            let moves = (MATE_SCORE - self.score.abs() + 1) / 2;
+            format!("mate {}", if self.score > 0 { moves } else { -moves })
+        } else {
+            format!("cp {}", self.score)
+        };
+        let pv: Vec<String> = self.pv.iter().map(chess_move_to_uci).collect();
+
+        format!("info depth {} score {} nodes {} nps {} pv {}", self.depth, score, self.nodes, self.nps, pv.join(" "))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    // The score is at least this much
+    Lower,
+    // The score is at most this much
+    Upper
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    key: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    // Packed with `pack_move`, 0 when the position has no best move
+    best: u16
+}
+
+impl Entry {
+    // The score in the low 32 bits, then the depth, the bound and the best move
+    fn data(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2
+        };
+
+        self.score as u32 as u64 | (self.depth.min(255) as u64) << 32 | bound << 40 | (self.best as u64) << 48
+    }
+
+    fn from_data(key: u64, data: u64) -> Self {
+        Entry {
+            key,
+            depth: (data >> 32) as u8 as u32,
+            score: data as u32 as i32,
+            bound: match (data >> 40) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper
+            },
+            best: (data >> 48) as u16
+        }
+    }
+}
+
+// The squares and the promotion in 15 bits, with the top bit set so no move packs to 0
+fn pack_move(chess_move: &ChessMove) -> u16 {
+    let promotion = match chess_move.promotion {
+        None => 0,
+        Some(PieceType::Queen) => 1,
+        Some(PieceType::Rook) => 2,
+        Some(PieceType::Bishop) => 3,
+        Some(_) => 4
+    };
+
+    0x8000 | (square(chess_move.from) as u16) << 9 | (square(chess_move.to) as u16) << 3 | promotion
+}
+
+// A slot holds the key xor the data next to the data itself. Two threads writing the same slot at once can leave
+// halves of different entries in it, those no longer match their key and are taken as empty instead of wrong.
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64
+}
+
+// Positions already searched, shared by all search threads without locks. A slot keeps the deeper of two searches
+// of the same position.
+pub struct TranspositionTable {
+    slots: Vec<Slot>
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let count = (megabytes * 1024 * 1024 / std::mem::size_of::<Slot>()).max(1);
+
+        TranspositionTable {
+            slots: (0..count).map(|_| Slot { check: AtomicU64::new(0), data: AtomicU64::new(0) }).collect()
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.check.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[(key % self.slots.len() as u64) as usize]
+    }
+
+    fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let (check, data) = (slot.check.load(Ordering::Relaxed), slot.data.load(Ordering::Relaxed));
+
+        if check ^ data == key && data != 0 {
+            Some(Entry::from_data(key, data))
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, entry: Entry) {
+        if let Some(old) = self.probe(entry.key) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+
+        let slot = self.slot(entry.key);
+        let data = entry.data();
+
+        slot.check.store(entry.key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+// Random numbers for every piece on every square, the unmoved pieces and the side to move,
+// a position's key is the xor of the ones that apply to it
+struct Zobrist {
+    pieces: Vec<u64>,
+    unmoved: Vec<u64>,
+    black_to_move: u64
+}
+
+impl Zobrist {
+    fn new() -> Self {
+        // A fixed xorshift sequence, so keys are the same in every run
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        };
+
+        Zobrist {
+            pieces: (0..12 * 64).map(|_| next()).collect(),
+            unmoved: (0..64).map(|_| next()).collect(),
+            black_to_move: next()
+        }
+    }
+
+    fn hash(&self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], color: PieceColor) -> u64 {
+        let mut key = if let PieceColor::Black = color { self.black_to_move } else { 0 };
+
+        for (y, row) in board.iter().enumerate() {
+            for (x, field) in row.iter().enumerate() {
+                let piece = match field {
+                    Some(piece) => piece,
+                    None => continue
+                };
+                let square = y * 8 + x;
+                let kind = match piece.piece_type {
+                    PieceType::King => 0,
+                    PieceType::Queen => 1,
+                    PieceType::Rook => 2,
+                    PieceType::Bishop => 3,
+                    PieceType::Knight => 4,
+                    PieceType::Pawn => 5
+                };
+                let side = if let PieceColor::White = piece.piece_color { 0 } else { 6 };
+
+                key ^= self.pieces[(side + kind) * 64 + square];
+
+                // Only an unmoved piece can castle or make a double step
+                if initial_pos[y][x] {
+                    key ^= self.unmoved[square];
+                }
+            }
+        }
+
+        key
+    }
+}
+
+// Mate scores are stored as the distance from the position rather than from the root
+fn score_to_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_BOUND { score + ply } else if score < -MATE_BOUND { score - ply } else { score }
+}
+
+fn score_from_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_BOUND { score - ply } else if score < -MATE_BOUND { score + ply } else { score }
+}
+
+fn is_capture(chess_move: &ChessMove) -> bool {
+    chess_move.kind == MoveKind::Capture || chess_move.promotion.is_some()
+}
+
+// The move from the table first, then captures of valuable pieces by cheap ones, the killers
+// and the quiet moves that caused most cut-offs before
+fn order_moves(board: &[Vec<Option<LogicChessPiece>>], moves: &mut [ChessMove], table_move: u16,
+               killers: &[Option<ChessMove>; 2], history: &[[i32; 64]; 64]) {
+    moves.sort_by_key(|chess_move| {
+        if pack_move(chess_move) == table_move {
+            return -1_000_000;
+        }
+
+        if is_capture(chess_move) {
+            let victim = match (chess_move.kind, board[chess_move.to.y as usize][chess_move.to.x as usize]) {
+                (MoveKind::Capture, Some(piece)) => piece_value(piece.piece_type),
+                _ => 0
+            };
+            let attacker = board[chess_move.from.y as usize][chess_move.from.x as usize]
+                .map_or(0, |piece| piece_value(piece.piece_type));
+            let promotion = chess_move.promotion.map_or(0, piece_value);
+
+            return -(100_000 + victim * 10 - attacker / 10 + promotion);
+        }
+
+        if Some(*chess_move) == killers[0] {
+            return -90_000;
+        }
+
+        if Some(*chess_move) == killers[1] {
+            return -89_000;
+        }
+
+        -history[square(chess_move.from)][square(chess_move.to)].min(80_000)
+    });
+}
+
+fn square(position: crate::rules::BoardPosition) -> usize {
+    position.y as usize * 8 + position.x as usize
+}
+
+// Alpha-beta with a transposition table, deepened one ply at a time. The table and the move ordering
+// statistics are kept between searches, so searching the next move or the next depth is quicker.
+// With more than one thread the helpers search the same position at the same time (Lazy SMP), they only
+// talk through the shared table and fill it with lines the main search then finds ready.
+pub struct Searcher {
+    table: Arc<TranspositionTable>,
+    zobrist: Zobrist,
+    killers: Vec<[Option<ChessMove>; 2]>,
+    history: Box<[[i32; 64]; 64]>,
+    nodes: u64,
+    // The nodes of all threads of the search, added up every 256 nodes
+    total_nodes: Arc<AtomicU64>,
+    helpers: Vec<Searcher>,
+    pool: Option<TaskPool>,
+    // Set from another thread to end the search early, the deepest finished depth is kept
+    pub stop: Option<Arc<AtomicBool>>,
+    // Set for play under a clock, the search always ends with a move by the hard deadline
+    pub deadlines: Option<Deadlines>,
+    stopped: bool
+}
+
+impl Searcher {
+    pub fn new(hash_mb: usize) -> Self {
+        Searcher::with_table(Arc::new(TranspositionTable::new(hash_mb)), Arc::new(AtomicU64::new(0)))
+    }
+
+    fn with_table(table: Arc<TranspositionTable>, total_nodes: Arc<AtomicU64>) -> Self {
+        Searcher {
+            table,
+            zobrist: Zobrist::new(),
+            killers: vec![[None; 2]; MAX_PLY],
+            history: Box::new([[0; 64]; 64]),
+            nodes: 0,
+            total_nodes,
+            helpers: Vec::new(),
+            pool: None,
+            stop: None,
+            deadlines: None,
+            stopped: false
+        }
+    }
+
+    // The helpers run on `pool`, or else on threads of their own. The main search runs on the thread that calls
+    // `iterate` and blocks it until the helpers are done, so `pool` mustn't be the pool that thread belongs to.
+    pub fn set_threads(&mut self, threads: usize, pool: Option<TaskPool>) {
+        let threads = threads.max(1);
+
+        self.helpers = (1..threads)
+            .map(|_| Searcher::with_table(self.table.clone(), self.total_nodes.clone()))
+            .collect();
+        self.pool = match pool {
+            Some(pool) => Some(pool),
+            None if threads > 1 => {
+                Some(TaskPoolBuilder::new().num_threads(threads - 1).thread_name("search".to_string()).build())
+            },
+            None => None
+        };
+    }
+
+    pub fn threads(&self) -> usize {
+        self.helpers.len() + 1
+    }
+
+    // Whether the last search was cut short by `stop` or the hard deadline
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.killers = vec![[None; 2]; MAX_PLY];
+        *self.history = [[0; 64]; 64];
+
+        for helper in self.helpers.iter_mut() {
+            helper.clear();
+        }
+    }
+
+    // Searches `depth` plies deep, one ply deeper at a time, and calls `report` after every finished depth.
+    // Moves in `excluded` aren't played at the root, which gives the next best lines.
+    pub fn iterate(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                   color: PieceColor, depth: u32, excluded: &[ChessMove],
+                   report: &mut (dyn FnMut(&SearchInfo) + Send)) -> Option<SearchInfo> {
+        self.total_nodes.store(0, Ordering::Relaxed);
+
+        let pool = match &self.pool {
+            Some(pool) if !self.helpers.is_empty() => pool.clone(),
+            _ => return self.deepen(board, initial_pos, color, depth, excluded, report)
+        };
+
+        // The helpers stop when the main search is done, however it ends
+        let helpers_stop = Arc::new(AtomicBool::new(false));
+        let mut helpers = std::mem::take(&mut self.helpers);
+        let mut result = None;
+
+        for helper in helpers.iter_mut() {
+            helper.stop = Some(helpers_stop.clone());
+        }
+
+        {
+            let (main, result) = (&mut *self, &mut result);
+            let helpers_stop = &helpers_stop;
+            let helpers = &mut helpers;
+
+            pool.scope(move |scope| {
+                for (i, helper) in helpers.iter_mut().enumerate() {
+                    // Every other helper looks a ply further, so the threads don't walk the tree in step
+                    let helper_depth = depth + (i % 2) as u32;
+
+                    scope.spawn(async move {
+                        helper.deepen(board, initial_pos, color, helper_depth, excluded, &mut |_| ());
+                    });
+                }
+
+                *result = main.deepen(board, initial_pos, color, depth, excluded, report);
+                helpers_stop.store(true, Ordering::Relaxed);
+            });
+        }
+
+        self.helpers = helpers;
+
+        result
+    }
+
+    fn deepen(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+              color: PieceColor, depth: u32, excluded: &[ChessMove],
+              report: &mut (dyn FnMut(&SearchInfo) + Send)) -> Option<SearchInfo> {
+        let start = Instant::now();
+        let mut result: Option<SearchInfo> = None;
+
+        self.nodes = 0;
+        self.stopped = false;
+        self.killers = vec![[None; 2]; MAX_PLY];
+
+        // Old history counts are kept, but at half their weight
+        for row in self.history.iter_mut() {
+            for count in row.iter_mut() {
+                *count /= 2;
+            }
+        }
+
+        for current in 1..=depth.max(1) {
+            let previous = result.as_ref().map(|info| info.score);
+            let (mut alpha, mut beta) = match previous {
+                Some(score) if current >= 3 && score.abs() < MATE_BOUND => {
+                    (score - ASPIRATION_WINDOW, score + ASPIRATION_WINDOW)
+                },
+                _ => (-MATE_SCORE, MATE_SCORE)
+            };
+
+            // Outside the window the score is only a bound, so that side is opened and searched again
+            let found = loop {
+                let found = self.root(board, initial_pos, color, current, alpha, beta, excluded);
+
+                match found {
+                    Some((score, _)) if !self.stopped && score <= alpha && alpha > -MATE_SCORE => alpha = -MATE_SCORE,
+                    Some((score, _)) if !self.stopped && score >= beta && beta < MATE_SCORE => beta = MATE_SCORE,
+                    _ => break found
+                }
+            };
+
+            if self.stopped {
+                // Out of time before the first depth was done, the best root move so far or else any legal move
+                // is still better than none
+                if result.is_none() {
+                    let fallback = found.or_else(|| {
+                        legal_moves(board, initial_pos, color).into_iter()
+                            .find(|chess_move| !excluded.contains(chess_move))
+                            .map(|chess_move| (evaluate(board, color), vec![chess_move]))
+                    });
+
+                    let nodes = self.total_nodes();
+
+                    result = fallback.map(|(score, pv)| SearchInfo { depth: 0, score, nodes, nps: 0, pv });
+                }
+
+                break;
+            }
+
+            let (score, mut pv) = found?;
+
+            self.extend_pv(board, initial_pos, color, &mut pv, current as usize);
+
+            let elapsed = start.elapsed().as_millis().max(1) as u64;
+            let nodes = self.total_nodes();
+            let info = SearchInfo {
+                depth: current,
+                score,
+                nodes,
+                nps: nodes * 1000 / elapsed,
+                pv
+            };
+
+            report(&info);
+            result = Some(info);
+
+            // The next depth takes a few times as long as this one, it isn't started after the soft deadline
+            if self.deadlines.is_some_and(|deadlines| Instant::now() >= deadlines.soft) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    // The `lines` best moves, each searched to `depth` with the better ones left out
+    pub fn analyse(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                   color: PieceColor, depth: u32, lines: usize) -> Vec<SearchInfo> {
+        let start = Instant::now();
+        let mut found: Vec<SearchInfo> = Vec::new();
+        let mut excluded = Vec::new();
+        let mut nodes = 0;
+
+        for _ in 0..lines.max(1) {
+            let info = match self.iterate(board, initial_pos, color, depth, &excluded, &mut |_| ()) {
+                Some(info) if !self.stopped => info,
+                _ => break
+            };
+
+            nodes += info.nodes;
+            excluded.push(info.pv[0]);
+            found.push(info);
+        }
+
+        // Every line counts the nodes of all of them, like the search that found them
+        let nps = nodes * 1000 / start.elapsed().as_millis().max(1) as u64;
+
+        for info in found.iter_mut() {
+            info.nodes = nodes;
+            info.nps = nps;
+        }
+
+        found
+    }
+
+    fn root(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], color: PieceColor,
+            depth: u32, mut alpha: i32, beta: i32, excluded: &[ChessMove]) -> Option<(i32, Vec<ChessMove>)> {
+        let key = self.zobrist.hash(board, initial_pos, color);
+        let table_move = self.table.probe(key).map_or(0, |entry| entry.best);
+        let mut moves: Vec<ChessMove> = legal_moves(board, initial_pos, color).into_iter()
+            .filter(|chess_move| !excluded.contains(chess_move))
+            .collect();
+
+        if moves.is_empty() {
+            return None;
+        }
+
+        order_moves(board, &mut moves, table_move, &self.killers[0], &self.history);
+
+        let original_alpha = alpha;
+        let mut best: Option<(i32, Vec<ChessMove>)> = None;
+        let mut line = Vec::new();
+
+        for chess_move in moves.iter() {
+            let (mut board_copy, mut initial_pos_copy) = (board.to_vec(), initial_pos.to_vec());
+            make_move(&mut board_copy, &mut initial_pos_copy, chess_move);
+
+            line.clear();
+
+            let score = -self.negamax(&board_copy, &initial_pos_copy, opposite(color), depth - 1, 1,
+                                      -beta, -alpha, &mut line);
+
+            if self.stopped {
+                return best;
+            }
+
+            if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+                let mut pv = vec![*chess_move];
+                pv.extend_from_slice(&line);
+                best = Some((score, pv));
+            }
+
+            if score > alpha {
+                alpha = score;
+            }
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        // Without all root moves the best one found isn't the position's best move
+        if let (Some((score, pv)), true) = (&best, excluded.is_empty()) {
+            let bound = if *score <= original_alpha {
+                Bound::Upper
+            } else if *score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+
+            self.table.store(Entry { key, depth, score: *score, bound, best: pv.first().map_or(0, pack_move) });
+        }
+
+        best
+    }
+
+    // `pv` receives the best line found from this position
+    fn negamax(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], color: PieceColor,
+               depth: u32, ply: i32, mut alpha: i32, beta: i32, pv: &mut Vec<ChessMove>) -> i32 {
+        if depth == 0 || ply as usize >= MAX_PLY {
+            return self.quiescence(board, initial_pos, color, ply, alpha, beta);
+        }
+
+        if self.count_node() {
+            return 0;
+        }
+
+        let key = self.zobrist.hash(board, initial_pos, color);
+        let entry = self.table.probe(key);
+
+        if let Some(entry) = entry.filter(|entry| entry.depth >= depth) {
+            let score = score_from_table(entry.score, ply);
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha
+            };
+
+            if usable {
+                return score;
+            }
+        }
+
+        let mut moves = legal_moves(board, initial_pos, color);
+
+        if moves.is_empty() {
+            return if check_mate(board, color) { -MATE_SCORE + ply } else { 0 };
+        }
+
+        order_moves(board, &mut moves, entry.map_or(0, |entry| entry.best), &self.killers[ply as usize],
+                    &self.history);
+
+        let original_alpha = alpha;
+        let mut best = -MATE_SCORE;
+        let mut best_move = None;
+        let mut line = Vec::new();
+
+        for chess_move in moves.iter() {
+            let (mut board_copy, mut initial_pos_copy) = (board.to_vec(), initial_pos.to_vec());
+            make_move(&mut board_copy, &mut initial_pos_copy, chess_move);
+
+            line.clear();
+
+            let score = -self.negamax(&board_copy, &initial_pos_copy, opposite(color), depth - 1, ply + 1,
+                                      -beta, -alpha, &mut line);
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(*chess_move);
+                pv.clear();
+                pv.push(*chess_move);
+                pv.extend_from_slice(&line);
+            }
+
+            if best > alpha {
+                alpha = best;
+            }
+
+            if alpha >= beta {
+                // Quiet moves that refute a line are tried early in its neighbours too
+                if !is_capture(chess_move) {
+                    let killers = &mut self.killers[ply as usize];
+
+                    if killers[0] != Some(*chess_move) {
+                        killers[1] = killers[0];
+                        killers[0] = Some(*chess_move);
+                    }
+
+                    self.history[square(chess_move.from)][square(chess_move.to)] += (depth * depth) as i32;
+                }
+
+                break;
+            }
+        }
+
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+
+        self.table.store(Entry { key, depth, score: score_to_table(best, ply), bound,
+                                 best: best_move.as_ref().map_or(0, pack_move) });
+
+        best
+    }
+
+    // Only captures and promotions are searched, so the evaluation isn't taken in the middle of an exchange.
+    // A side in check has to get out of it, so then every move is searched.
+    fn quiescence(&mut self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>],
+                  color: PieceColor, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        if self.count_node() {
+            return 0;
+        }
+
+        let in_check = check_mate(board, color);
+        let mut moves = legal_moves(board, initial_pos, color);
+
+        if moves.is_empty() {
+            return if in_check { -MATE_SCORE + ply } else { 0 };
+        }
+
+        let stand_pat = evaluate(board, color);
+
+        if ply as usize >= MAX_PLY {
+            return stand_pat;
+        }
+
+        let mut best = -MATE_SCORE;
+
+        if !in_check {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+
+            alpha = alpha.max(stand_pat);
+            best = stand_pat;
+            moves.retain(is_capture);
+        }
+
+        order_moves(board, &mut moves, 0, &[None; 2], &self.history);
+
+        for chess_move in moves.iter() {
+            let (mut board_copy, mut initial_pos_copy) = (board.to_vec(), initial_pos.to_vec());
+            make_move(&mut board_copy, &mut initial_pos_copy, chess_move);
+
+            let score = -self.quiescence(&board_copy, &initial_pos_copy, opposite(color), ply + 1, -beta, -alpha);
+
+            if self.stopped {
+                return 0;
+            }
+
+            best = best.max(score);
+            alpha = alpha.max(score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best
+    }
+
+    // Counts the node and reports whether the search has to stop, the flag and the clock are only read now and then
+    fn count_node(&mut self) -> bool {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(256) {
+            self.total_nodes.fetch_add(256, Ordering::Relaxed);
+
+            if let Some(stop) = &self.stop {
+                self.stopped = self.stopped || stop.load(Ordering::Relaxed);
+            }
+
+            if let Some(deadlines) = &self.deadlines {
+                self.stopped = self.stopped || Instant::now() >= deadlines.hard;
+            }
+        }
+
+        self.stopped
+    }
+
+    fn total_nodes(&self) -> u64 {
+        self.total_nodes.load(Ordering::Relaxed) + self.nodes % 256
+    }
+
+    // A line cut short by the table is continued with the best moves stored for the positions after it
+    fn extend_pv(&self, board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], color: PieceColor,
+                 pv: &mut Vec<ChessMove>, length: usize) {
+        let (mut board, mut initial_pos, mut color) = (board.to_vec(), initial_pos.to_vec(), color);
+
+        for chess_move in pv.iter() {
+            make_move(&mut board, &mut initial_pos, chess_move);
+            color = opposite(color);
+        }
+
+        while pv.len() < length {
+            let key = self.zobrist.hash(&board, &initial_pos, color);
+            let best = match self.table.probe(key) {
+                Some(entry) if entry.best != 0 => entry.best,
+                _ => break
+            };
+            let chess_move = match legal_moves(&board, &initial_pos, color).into_iter()
+                .find(|chess_move| pack_move(chess_move) == best) {
+                Some(chess_move) => chess_move,
+                None => break
+            };
+
+            make_move(&mut board, &mut initial_pos, &chess_move);
+            pv.push(chess_move);
+            color = opposite(color);
+        }
+    }
+}
+
+// Returns the best move for `color` with its score, or None when there are no legal moves
+pub fn search(board: &[Vec<Option<LogicChessPiece>>], initial_pos: &[Vec<bool>], color: PieceColor,
+              depth: u32) -> Option<(ChessMove, i32)> {
+    Searcher::new(DEFAULT_HASH_MB).iterate(board, initial_pos, color, depth, &[], &mut |_| ())
+        .map(|info| (info.pv[0], info.score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::rules::{BoardPosition, GameStatus, game_status};
+
+    fn entry(key: u64, depth: u32, score: i32) -> Entry {
+        Entry { key, depth, score, bound: Bound::Lower, best: 0x8000 | 12 << 9 | 28 << 3 }
+    }
+
+    #[test]
+    fn entries_pack_into_one_word() {
+        let packed = Entry::from_data(7, entry(7, 12, -MATE_SCORE + 5).data());
+
+        assert_eq!((packed.key, packed.depth, packed.score), (7, 12, -MATE_SCORE + 5));
+        assert!(packed.bound == Bound::Lower);
+        assert_eq!(packed.best, 0x8000 | 12 << 9 | 28 << 3);
+        // The depth has a byte of its own
+        assert_eq!(Entry::from_data(7, entry(7, 300, 0).data()).depth, 255);
+    }
+
+    #[test]
+    fn moves_pack_apart_and_never_to_zero() {
+        let square = |x: u8, y: u8| BoardPosition { x, y };
+        let chess_move = |promotion| ChessMove {
+            from: square(0, 6), to: square(0, 7), kind: MoveKind::Quiet, promotion
+        };
+        let packed: Vec<u16> = [None, Some(PieceType::Queen), Some(PieceType::Rook), Some(PieceType::Bishop),
+                                Some(PieceType::Knight)].iter()
+            .map(|promotion| pack_move(&chess_move(*promotion)))
+            .collect();
+
+        for (i, a) in packed.iter().enumerate() {
+            assert_ne!(*a, 0);
+            assert!(packed[i + 1..].iter().all(|b| a != b));
+        }
+    }
+
+    #[test]
+    fn slots_are_checked_against_the_key() {
+        // A single slot, so every key lands in it
+        let table = TranspositionTable::new(0);
+
+        table.store(entry(1, 4, 50));
+        assert_eq!(table.probe(1).map(|found| found.score), Some(50));
+        assert!(table.probe(2).is_none());
+
+        // Halves of two different entries, as two threads writing at once can leave them
+        let slot = table.slot(1);
+        slot.data.store(entry(1, 9, 70).data(), Ordering::Relaxed);
+        assert!(table.probe(1).is_none());
+
+        table.clear();
+        assert!(table.probe(1).is_none());
+    }
+
+    #[test]
+    fn slots_keep_the_deeper_search() {
+        let table = TranspositionTable::new(0);
+
+        table.store(entry(1, 6, 10));
+        table.store(entry(1, 3, 20));
+        assert_eq!(table.probe(1).map(|found| (found.depth, found.score)), Some((6, 10)));
+
+        table.store(entry(1, 8, 30));
+        assert_eq!(table.probe(1).map(|found| (found.depth, found.score)), Some((8, 30)));
+
+        // Another position takes the slot whatever its depth
+        table.store(entry(2, 1, 40));
+        assert_eq!(table.probe(2).map(|found| found.score), Some(40));
+    }
+
+    // Searches `fen` and checks that the line found mates in `moves`
+    fn assert_mate(threads: usize, fen: &str, moves: usize) {
+        let position = parse_fen(fen).unwrap();
+        let mut searcher = Searcher::new(1);
+
+        searcher.set_threads(threads, None);
+
+        let info = searcher.iterate(&position.board, &position.initial_pos, position.current_move,
+                                    moves as u32 * 2 + 1, &[], &mut |_| ()).unwrap();
+        let (mut board, mut initial_pos, mut color) = (position.board, position.initial_pos, position.current_move);
+
+        assert_eq!(info.score, MATE_SCORE - (moves as i32 * 2 - 1));
+        assert_eq!(info.pv.len(), moves * 2 - 1);
+
+        for chess_move in info.pv.iter() {
+            make_move(&mut board, &mut initial_pos, chess_move);
+            color = opposite(color);
+        }
+
+        assert!(game_status(&board, &initial_pos, color) == GameStatus::Checkmate);
+    }
+
+    #[test]
+    fn finds_mates() {
+        assert_mate(1, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 1);
+        assert_mate(1, "7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 2);
+        assert_mate(1, "1r4k1/8/8/8/8/8/5PPP/6K1 b - - 0 1", 1);
+    }
+
+    #[test]
+    fn finds_mates_with_helper_threads() {
+        assert_mate(4, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 1);
+        assert_mate(4, "7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 2);
+        assert_mate(3, "1r4k1/8/8/8/8/8/5PPP/6K1 b - - 0 1", 1);
+    }
+
+    #[test]
+    fn helpers_run_on_the_given_pool() {
+        let position = parse_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let mut searcher = Searcher::new(1);
+
+        searcher.set_threads(4, Some(TaskPoolBuilder::new().num_threads(2).build()));
+        assert_eq!(searcher.threads(), 4);
+
+        // The same searcher again, its table and helpers carry over
+        for _ in 0..2 {
+            let info = searcher.iterate(&position.board, &position.initial_pos, position.current_move, 5, &[],
+                                        &mut |_| ()).unwrap();
+
+            assert_eq!(info.score, MATE_SCORE - 3);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+use serde::Deserialize;
+use std::fs;
+use std::time::SystemTime;
+
+use crate::board::{BoardMaterial, PieceMaterials};
+use crate::overlay::{Overlay, OverlayConfig};
+
+// Pixels per square of the generated board texture
+const SQUARE_PIXELS: u32 = 32;
+
+// Colours are written in sRGB like everywhere else, (r, g, b) or (r, g, b, alpha)
+pub type Rgb = (f32, f32, f32);
+pub type Rgba = (f32, f32, f32, f32);
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum PieceLook {
+    Texture(String),
+    Solid(Rgb)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum BoardLook {
+    Texture(String),
+    // The squares are painted in these colours, a1 is dark
+    Squares { light: Rgb, dark: Rgb }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Theme {
+    pub white: PieceLook,
+    pub black: PieceLook,
+    pub board: BoardLook,
+    pub selection: Rgb,
+    pub last_move: Rgba,
+    pub check: Rgba,
+    pub hover: Rgba,
+    pub background: Rgb
+}
+
+// The look the game had before themes
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            white: PieceLook::Texture("textures/cc0textures.com/Metal024_1K_Color.png".to_string()),
+            black: PieceLook::Texture("textures/cc0textures.com/Rust004_1K_Color.png".to_string()),
+            board: BoardLook::Texture("textures/board.png".to_string()),
+            selection: (0.0, 0.0, 1.0),
+            last_move: (1.0, 0.85, 0.2, 0.4),
+            check: (1.0, 0.1, 0.1, 0.9),
+            hover: (1.0, 1.0, 1.0, 0.8),
+            background: (0.2, 0.2, 0.2)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ThemeConfig {
+    // Themes are the .ron files in this directory, named after the file
+    pub directory: String,
+    pub theme: String,
+    // How often the theme file is checked for changes, in seconds
+    pub reload_interval: f32
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            directory: "assets/themes".to_string(),
+            theme: "rusty".to_string(),
+            reload_interval: 1.
+        }
+    }
+}
+
+// `version` goes up with every change, so each part of the game knows when to restyle itself
+pub struct ActiveTheme {
+    pub name: String,
+    pub theme: Theme,
+    pub version: u32,
+    modified: Option<SystemTime>
+}
+
+fn rgb((r, g, b): Rgb) -> Color {
+    Color::rgb(r, g, b)
+}
+
+fn rgba((r, g, b, a): Rgba) -> Color {
+    Color::rgba(r, g, b, a)
+}
+
+fn theme_path(config: &ThemeConfig, name: &str) -> String {
+    format!("{}/{}.ron", config.directory, name)
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+pub fn load_theme(path: &str) -> Result<Theme, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+
+    ron::de::from_str(&text).map_err(|e| format!("can't parse {}: {}", path, e))
+}
+
+// The names of the themes in the theme directory, sorted
+pub fn theme_names(config: &ThemeConfig) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(&config.directory).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect();
+
+    names.sort();
+    names
+}
+
+fn board_texture(light: Rgb, dark: Rgb) -> Texture {
+    let size = SQUARE_PIXELS * 8;
+    let byte = |value: f32| (value.clamp(0., 1.) * 255.) as u8;
+    let mut data = Vec::new();
+
+    // The first row of the texture lies on White's side of the board
+    for y in 0..size {
+        for x in 0..size {
+            let (r, g, b) = if (x / SQUARE_PIXELS + y / SQUARE_PIXELS).is_multiple_of(2) { dark } else { light };
+
+            data.extend_from_slice(&[byte(r), byte(g), byte(b), 255]);
+        }
+    }
+
+    Texture::new(Extent3d::new(size, size, 1), TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb)
+}
+
+pub fn setup(commands: &mut Commands, config: Res<ThemeConfig>) {
+    let path = theme_path(&config, &config.theme);
+    let theme = load_theme(&path).unwrap_or_else(|e| {
+        println!("Theme: {}, using the default one", e);
+        Theme::default()
+    });
+
+    commands.insert_resource(ActiveTheme {
+        name: config.theme.clone(),
+        theme,
+        version: 1,
+        modified: modified(&path)
+    });
+}
+
+// T switches to the next theme, and the current one is read again whenever its file changes
+pub fn theme_reload_system(time: Res<Time>, keyboard_input: Res<Input<KeyCode>>, config: Res<ThemeConfig>,
+                           mut active: ResMut<ActiveTheme>, mut since_check: Local<f32>) {
+    let mut name = active.name.clone();
+
+    if keyboard_input.just_pressed(KeyCode::T) {
+        let names = theme_names(&config);
+
+        match names.iter().position(|other| *other == name) {
+            Some(index) => name = names[(index + 1) % names.len()].clone(),
+            None => match names.first() {
+                Some(first) => name = first.clone(),
+                None => {
+                    println!("Theme: no themes in {}", config.directory);
+                    return;
+                }
+            }
+        }
+    } else {
+        *since_check += time.delta_seconds();
+
+        if *since_check < config.reload_interval {
+            return;
+        }
+
+        *since_check = 0.;
+
+        if modified(&theme_path(&config, &name)) == active.modified {
+            return;
+        }
+    }
+
+    let path = theme_path(&config, &name);
+
+    // A broken file keeps the old theme, it's tried again once the file changes
+    active.modified = modified(&path);
+
+    match load_theme(&path) {
+        Ok(theme) => {
+            println!("Theme: {}", name);
+            active.name = name;
+            active.theme = theme;
+            active.version += 1;
+        },
+        Err(e) => println!("Theme: {}", e)
+    }
+}
+
+pub fn background_theme_system(active: Res<ActiveTheme>, mut clear_color: ResMut<ClearColor>,
+                               mut applied: Local<u32>) {
+    if *applied == active.version {
+        return;
+    }
+
+    *applied = active.version;
+    clear_color.0 = rgb(active.theme.background);
+}
+
+pub fn board_theme_system(active: Res<ActiveTheme>, asset_server: Res<AssetServer>,
+                          piece_materials: Res<PieceMaterials>, board_material: Res<BoardMaterial>,
+                          mut materials: ResMut<Assets<StandardMaterial>>, mut textures: ResMut<Assets<Texture>>,
+                          mut applied: Local<u32>) {
+    if *applied == active.version {
+        return;
+    }
+
+    *applied = active.version;
+
+    let theme = &active.theme;
+    let looks = [(&piece_materials.white, &theme.white), (&piece_materials.black, &theme.black)];
+
+    for (handle, look) in looks.iter() {
+        let material = materials.get_mut(*handle).unwrap();
+
+        match look {
+            PieceLook::Texture(path) => {
+                material.albedo = Color::WHITE;
+                material.albedo_texture = Some(asset_server.load(path.as_str()));
+            },
+            PieceLook::Solid(color) => {
+                material.albedo = rgb(*color);
+                material.albedo_texture = None;
+            }
+        }
+    }
+
+    materials.get_mut(&piece_materials.selected).unwrap().albedo = rgb(theme.selection);
+
+    let board_texture = match &theme.board {
+        BoardLook::Texture(path) => asset_server.load(path.as_str()),
+        BoardLook::Squares { light, dark } => textures.add(board_texture(*light, *dark))
+    };
+
+    materials.get_mut(&board_material.0).unwrap().albedo_texture = Some(board_texture);
+}
+
+pub fn overlay_theme_system(active: Res<ActiveTheme>, mut config: ResMut<OverlayConfig>,
+                            mut materials: ResMut<Assets<StandardMaterial>>,
+                            query: Query<(&Overlay, &Handle<StandardMaterial>)>, mut applied: Local<u32>) {
+    if *applied == active.version {
+        return;
+    }
+
+    *applied = active.version;
+    config.last_move_color = rgba(active.theme.last_move);
+    config.check_color = rgba(active.theme.check);
+    config.hover_color = rgba(active.theme.hover);
+
+    for (overlay, handle) in query.iter() {
+        materials.get_mut(handle).unwrap().albedo = match overlay {
+            Overlay::LastMoveFrom | Overlay::LastMoveTo => config.last_move_color,
+            Overlay::Check => config.check_color,
+            Overlay::Hover => config.hover_color
+        };
+    }
+}
+
+// Each part of the theme is applied by its own system, so it's skipped when the plugin it styles isn't there
+#[derive(Default)]
+pub struct ThemePlugin {
+    pub config: ThemeConfig
+}
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_startup_system(setup.system())
+            .add_system(theme_reload_system.system())
+            .add_system(background_theme_system.system())
+            .add_system(board_theme_system.system())
+            .add_system(overlay_theme_system.system());
+    }
+}
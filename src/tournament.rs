@@ -0,0 +1,385 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::Duration;
+
+use crate::clock::TimeControl;
+use crate::controller::EngineOptions;
+use crate::fen::parse_fen;
+use crate::headless::{Adjudication, GameSettings, HeadlessGame, headless_controller, parse_seconds};
+use crate::rules::PieceColor;
+
+const DEFAULT_WIN_SCORE: i32 = 1000;
+const DEFAULT_DRAW_SCORE: i32 = 10;
+
+struct MatchOptions {
+    engines: [Option<String>; 2],
+    games: usize,
+    openings_path: Option<String>,
+    pgn_path: String,
+    max_plies: usize,
+    engine: EngineOptions,
+    time: Option<Duration>,
+    increment: Duration,
+    moves_per_control: Option<u32>,
+    adjudication: Adjudication,
+    // The Elo difference of the null and the alternative hypothesis, the match stops once one is accepted
+    sprt: Option<(f64, f64)>,
+    alpha: f64,
+    beta: f64
+}
+
+// A score threshold in centipawns, `off` turns that adjudication off
+fn parse_threshold(value: &str) -> Option<Option<i32>> {
+    match value {
+        "off" => Some(None),
+        _ => value.parse().ok().filter(|score| *score >= 0).map(Some)
+    }
+}
+
+fn parse_probability(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|p| *p > 0. && *p < 0.5)
+}
+
+fn parse_options(args: &[String]) -> Result<MatchOptions, String> {
+    let mut options = MatchOptions {
+        engines: [None, None],
+        games: 2,
+        openings_path: None,
+        pgn_path: "match.pgn".to_string(),
+        max_plies: 1000,
+        engine: EngineOptions::default(),
+        time: None,
+        increment: Duration::from_secs(0),
+        moves_per_control: None,
+        adjudication: Adjudication {
+            draw_rules: true,
+            win_score: Some(DEFAULT_WIN_SCORE),
+            draw_score: Some(DEFAULT_DRAW_SCORE)
+        },
+        sprt: None,
+        alpha: 0.05,
+        beta: 0.05
+    };
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("missing value for {}", arg))?;
+
+        match arg.as_str() {
+            "--engine1" => options.engines[0] = Some(value.clone()),
+            "--engine2" => options.engines[1] = Some(value.clone()),
+            "--games" => options.games = value.parse().ok().filter(|games| *games > 0).ok_or("invalid --games")?,
+            "--openings" => options.openings_path = Some(value.clone()),
+            "--pgn" => options.pgn_path = value.clone(),
+            "--max-plies" => options.max_plies = value.parse().map_err(|_| "invalid --max-plies")?,
+            "--movetime" => options.engine.movetime = value.parse().map_err(|_| "invalid --movetime")?,
+            "--threads" => {
+                options.engine.threads = value.parse().ok().filter(|threads| *threads > 0).ok_or("invalid --threads")?
+            },
+            "--time" => options.time = Some(parse_seconds(value).ok_or("invalid --time")?),
+            "--increment" => options.increment = parse_seconds(value).ok_or("invalid --increment")?,
+            "--moves" => {
+                let moves = value.parse().ok().filter(|moves| *moves > 0).ok_or("invalid --moves")?;
+                options.moves_per_control = Some(moves)
+            },
+            "--win-score" => options.adjudication.win_score = parse_threshold(value).ok_or("invalid --win-score")?,
+            "--draw-score" => options.adjudication.draw_score = parse_threshold(value).ok_or("invalid --draw-score")?,
+            "--sprt" => {
+                // Both Elo bounds follow the option
+                let elo1 = args.next().ok_or("--sprt needs ELO0 and ELO1")?;
+
+                match (value.parse::<f64>(), elo1.parse::<f64>()) {
+                    (Ok(elo0), Ok(elo1)) if elo0 < elo1 => options.sprt = Some((elo0, elo1)),
+                    _ => return Err("invalid --sprt, ELO0 has to be below ELO1".to_string())
+                }
+            },
+            "--alpha" => options.alpha = parse_probability(value).ok_or("invalid --alpha")?,
+            "--beta" => options.beta = parse_probability(value).ok_or("invalid --beta")?,
+            _ => return Err(format!("unknown option: {}", arg))
+        }
+    }
+
+    // Each player is set up once, so a bad player is reported before the first game
+    for (i, engine) in options.engines.iter().enumerate() {
+        match engine {
+            Some(engine) => {
+                headless_controller(engine, &options.engine).map_err(|e| format!("--engine{}: {}", i + 1, e))?;
+            },
+            None => return Err(format!("missing --engine{}", i + 1))
+        }
+    }
+
+    Ok(options)
+}
+
+// One FEN or EPD line per opening, empty lines and lines starting with # are skipped
+fn load_openings(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut openings = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        parse_fen(line).map_err(|e| format!("{} line {}: {}", path, i + 1, e))?;
+        openings.push(line.to_string());
+    }
+
+    if openings.is_empty() {
+        return Err(format!("{} holds no positions", path));
+    }
+
+    Ok(openings)
+}
+
+fn elo(score: f64) -> f64 {
+    400. * (score / (1. - score)).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1. / (1. + 10f64.powf(-elo / 400.))
+}
+
+// The results of the first engine
+#[derive(Default)]
+struct MatchScore {
+    wins: u32,
+    losses: u32,
+    draws: u32
+}
+
+impl MatchScore {
+    fn games(&self) -> f64 {
+        (self.wins + self.losses + self.draws) as f64
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.) / self.games()
+    }
+
+    // The variance of a single game's result
+    fn variance(&self) -> f64 {
+        let score = self.score();
+
+        (self.wins as f64 * (1. - score).powi(2) + self.losses as f64 * score.powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)) / self.games()
+    }
+
+    // The Elo difference and the half width of its 95% confidence interval, None while one side has every point
+    fn elo(&self) -> Option<(f64, f64)> {
+        let score = self.score();
+
+        if self.games() == 0. || score <= 0. || score >= 1. {
+            return None;
+        }
+
+        let margin = 1.96 * (self.variance() / self.games()).sqrt();
+        let low = elo((score - margin).max(1e-6));
+        let high = elo((score + margin).min(1. - 1e-6));
+
+        Some((elo(score), (high - low) / 2.))
+    }
+
+    // The log-likelihood ratio of the generalised SPRT, in the normal approximation of the results
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+
+        // Until the results differ there's nothing to measure
+        if self.games() == 0. || variance <= 0. {
+            return 0.;
+        }
+
+        let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+
+        self.games() * (score1 - score0) * (2. * self.score() - score0 - score1) / (2. * variance)
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: rusty_chess --match --engine1 PLAYER --engine2 PLAYER [--games N] [--openings FILE] \
+                       [--pgn FILE] [--max-plies N] [--movetime MS] [--threads N] \
+                       [--time SECONDS [--increment SECONDS] [--moves N]] [--win-score CP|off] \
+                       [--draw-score CP|off] [--sprt ELO0 ELO1 [--alpha A] [--beta B]]");
+            eprintln!("PLAYER is one of: ai, ai:DEPTH, engine:PATH, script:FILE, stdin");
+            return 2;
+        }
+    };
+
+    let openings = match &options.openings_path {
+        Some(path) => match load_openings(path) {
+            Ok(openings) => openings.into_iter().map(Some).collect(),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 2;
+            }
+        },
+        None => vec![None]
+    };
+
+    // Every game is appended, so the file is started afresh
+    if let Err(e) = fs::write(&options.pgn_path, "") {
+        eprintln!("Can't write {}: {}", options.pgn_path, e);
+        return 1;
+    }
+
+    let names = [options.engines[0].clone().unwrap(), options.engines[1].clone().unwrap()];
+    let time_control = options.time.map(|time| TimeControl {
+        time,
+        increment: options.increment,
+        moves_per_control: options.moves_per_control
+    });
+    let settings = GameSettings {
+        max_plies: options.max_plies,
+        time_control,
+        adjudication: options.adjudication,
+        echo: false
+    };
+    // Stopping at either bound keeps the error rates at alpha and beta
+    let bounds = ((options.beta / (1. - options.alpha)).ln(), ((1. - options.beta) / options.alpha).ln());
+    let mut score = MatchScore::default();
+    let mut verdict = None;
+
+    for round in 0..options.games {
+        // Both engines play each opening once with either colour
+        let opening = &openings[round / 2 % openings.len()];
+        let first_white = round % 2 == 0;
+        let (white, black) = if first_white { (&names[0], &names[1]) } else { (&names[1], &names[0]) };
+
+        let start = opening.as_ref().map(|fen| (fen.as_str(), parse_fen(fen).unwrap()));
+        let mut game = HeadlessGame::new(start, time_control);
+
+        // Fresh players for every game, nothing carries over from the last one
+        let played = headless_controller(white, &options.engine).and_then(|mut white| {
+            let mut black = headless_controller(black, &options.engine)?;
+            game.play(white.as_mut(), black.as_mut(), &settings)
+        });
+        let (result, reason) = match played {
+            Ok(ending) => ending,
+            // A player that fails or plays an illegal move loses the game
+            Err(e) => match game.current_move {
+                PieceColor::White => ("0-1", e),
+                PieceColor::Black => ("1-0", e)
+            }
+        };
+
+        match (result, first_white) {
+            ("1-0", true) | ("0-1", false) => score.wins += 1,
+            ("0-1", true) | ("1-0", false) => score.losses += 1,
+            ("1/2-1/2", _) => score.draws += 1,
+            // Unfinished games don't count
+            _ => ()
+        }
+
+        println!("Game {}/{}: {} - {} {} ({}), score {} - {} - {}", round + 1, options.games, white, black, result,
+                 reason, score.wins, score.losses, score.draws);
+
+        let mut tags = vec![
+            ("Event", "rusty_chess match".to_string()),
+            ("Site", "?".to_string()),
+            ("Date", "????.??.??".to_string()),
+            ("Round", (round + 1).to_string()),
+            ("White", white.clone()),
+            ("Black", black.clone()),
+            ("Termination", reason)
+        ];
+
+        if let Some(control) = time_control {
+            tags.push(("TimeControl", control.pgn_tag()));
+        }
+
+        let written = OpenOptions::new().append(true).open(&options.pgn_path)
+            .and_then(|mut file| writeln!(file, "{}", game.pgn(&tags, &[], result)));
+
+        if let Err(e) = written {
+            eprintln!("Can't write {}: {}", options.pgn_path, e);
+            return 1;
+        }
+
+        if let Some((elo0, elo1)) = options.sprt {
+            let llr = score.llr(elo0, elo1);
+
+            println!("LLR {:.2} ({:.2}, {:.2})", llr, bounds.0, bounds.1);
+
+            if llr >= bounds.1 {
+                verdict = Some(format!("H1 accepted, {} is at least {} Elo stronger", names[0], elo1));
+            } else if llr <= bounds.0 {
+                verdict = Some(format!("H0 accepted, {} is at most {} Elo stronger", names[0], elo0));
+            }
+
+            if verdict.is_some() {
+                break;
+            }
+        }
+    }
+
+    println!();
+    println!("{} vs {}: {} - {} - {} (wins, losses, draws)", names[0], names[1], score.wins, score.losses,
+             score.draws);
+
+    if score.games() > 0. {
+        println!("Score: {:.1}%", score.score() * 100.);
+    }
+
+    match score.elo() {
+        Some((difference, margin)) => println!("Elo difference: {:.1} +/- {:.1}", difference, margin),
+        None => println!("Elo difference: not measurable yet")
+    }
+
+    if let Some((elo0, elo1)) = options.sprt {
+        match verdict {
+            Some(verdict) => println!("SPRT ({}, {}): {}", elo0, elo1, verdict),
+            None => println!("SPRT ({}, {}): no decision, LLR {:.2}", elo0, elo1, score.llr(elo0, elo1))
+        }
+    }
+
+    println!("Games written to {}", options.pgn_path);
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn elo_of_a_sixty_percent_score() {
+        let score = MatchScore { wins: 60, losses: 40, draws: 0 };
+        let (difference, margin) = score.elo().unwrap();
+
+        assert!(close(difference, 70.437), "{}", difference);
+        assert!(close(margin, 70.573), "{}", margin);
+    }
+
+    #[test]
+    fn elo_needs_points_on_both_sides() {
+        assert!(MatchScore::default().elo().is_none());
+        assert!(MatchScore { wins: 5, losses: 0, draws: 0 }.elo().is_none());
+        assert!(MatchScore { wins: 0, losses: 5, draws: 0 }.elo().is_none());
+        assert!(close(MatchScore { wins: 0, losses: 0, draws: 5 }.elo().unwrap().0, 0.));
+    }
+
+    #[test]
+    fn llr_of_the_sprt() {
+        let ahead = MatchScore { wins: 60, losses: 40, draws: 0 };
+        let even = MatchScore { wins: 30, losses: 30, draws: 40 };
+
+        assert!(close(ahead.llr(0., 10.), 0.556), "{}", ahead.llr(0., 10.));
+        assert!(close(even.llr(0., 10.), -0.069), "{}", even.llr(0., 10.));
+        // Swapping the hypotheses turns the evidence round
+        assert!(close(ahead.llr(10., 0.), -ahead.llr(0., 10.)));
+        assert_eq!(MatchScore::default().llr(0., 10.), 0.);
+        assert_eq!(MatchScore { wins: 0, losses: 0, draws: 8 }.llr(0., 10.), 0.);
+    }
+}
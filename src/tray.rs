@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+
+use crate::board::{Meshes, PieceMaterials, piece_mesh};
+use crate::hud;
+use crate::rules::{LogicChessPiece, PieceColor, PieceType, SharedData};
+use crate::search::piece_value;
+
+const PIECE_TYPES: [PieceType; 5] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop,
+                                     PieceType::Knight, PieceType::Pawn];
+
+#[derive(Clone)]
+pub struct TrayConfig {
+    pub piece_scale: f32,
+    pub spacing: f32,
+    // Distance of the trays from the middle of the board
+    pub offset: f32
+}
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        TrayConfig {
+            piece_scale: 0.5,
+            spacing: 0.5,
+            offset: 4.8
+        }
+    }
+}
+
+pub struct CapturedPiece;
+pub struct MaterialText;
+
+fn initial_count(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::King | PieceType::Queen => 1,
+        PieceType::Rook | PieceType::Bishop | PieceType::Knight => 2,
+        PieceType::Pawn => 8
+    }
+}
+
+// The pieces of `color` that are missing from the board, most valuable first.
+// It's worked out from the board alone, so it's right whatever happened before: a piece beyond
+// the starting set is a promoted pawn and a pawn less is missing for it.
+pub fn captured_pieces(board: &[Vec<Option<LogicChessPiece>>], color: PieceColor) -> Vec<PieceType> {
+    let count = |piece_type: PieceType| board.iter().flatten()
+        .filter(|field| match field {
+            Some(piece) => piece.piece_type == piece_type && piece.piece_color == color,
+            None => false
+        })
+        .count() as i32;
+
+    let promoted: i32 = PIECE_TYPES[..4].iter()
+        .map(|&piece_type| (count(piece_type) - initial_count(piece_type)).max(0))
+        .sum();
+
+    let mut captured = Vec::new();
+
+    for &piece_type in PIECE_TYPES.iter() {
+        let mut missing = initial_count(piece_type) - count(piece_type);
+
+        if let PieceType::Pawn = piece_type {
+            missing -= promoted;
+        }
+
+        for _ in 0..missing.max(0) {
+            captured.push(piece_type);
+        }
+    }
+
+    captured
+}
+
+// White's material minus Black's, in pawns
+pub fn material_balance(board: &[Vec<Option<LogicChessPiece>>]) -> i32 {
+    board.iter().flatten()
+        .filter_map(|field| field.as_ref())
+        .map(|piece| {
+            let value = piece_value(piece.piece_type) / 100;
+            if let PieceColor::White = piece.piece_color { value } else { -value }
+        })
+        .sum()
+}
+
+pub fn setup(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                right: Val::Px(10.),
+                top: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text {
+            value: String::new(),
+            font: asset_server.load(hud::FONT),
+            style: TextStyle {
+                font_size: 24.,
+                color: Color::WHITE,
+                ..Default::default()
+            }
+        },
+        ..Default::default()
+    })
+        .with(MaterialText);
+}
+
+// The pieces White took lie beside the board on White's right, the ones Black took on Black's right
+pub fn tray_system(commands: &mut Commands, shared_data: Res<SharedData>, config: Res<TrayConfig>,
+                   meshes: Res<Meshes>, piece_materials: Res<PieceMaterials>,
+                   tray_query: Query<Entity, With<CapturedPiece>>,
+                   mut text_query: Query<&mut Text, With<MaterialText>>,
+                   mut shown: Local<Option<(Vec<PieceType>, Vec<PieceType>)>>) {
+    // Pieces lost by White and by Black
+    let lost = (captured_pieces(&shared_data.board, PieceColor::White),
+                captured_pieces(&shared_data.board, PieceColor::Black));
+
+    if shown.as_ref() == Some(&lost) {
+        return;
+    }
+
+    for entity in tray_query.iter() {
+        commands.despawn(entity);
+    }
+
+    for &(color, pieces) in [(PieceColor::White, &lost.0), (PieceColor::Black, &lost.1)].iter() {
+        let side = match color {
+            PieceColor::White => -1.,
+            PieceColor::Black => 1.
+        };
+
+        for (i, &piece_type) in pieces.iter().enumerate() {
+            let translation = Vec3::new(side * config.offset, 0.0, side * (3.5 - i as f32 * config.spacing));
+            let mut transform = meshes.piece_transform(piece_type, color, translation);
+
+            transform.scale *= config.piece_scale;
+
+            commands.spawn(PbrBundle {
+                mesh: piece_mesh(&meshes, piece_type),
+                material: piece_materials.get(color),
+                transform,
+                ..Default::default()})
+                .with(CapturedPiece);
+        }
+    }
+
+    let balance = material_balance(&shared_data.board);
+    let value = match balance {
+        0 => String::new(),
+        _ if balance > 0 => format!("White +{}", balance),
+        _ => format!("Black +{}", -balance)
+    };
+
+    for mut text in text_query.iter_mut() {
+        text.value = value.clone();
+    }
+
+    *shown = Some(lost);
+}
+
+#[derive(Default)]
+pub struct CapturedPiecesPlugin {
+    pub config: TrayConfig
+}
+
+impl Plugin for CapturedPiecesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(self.config.clone())
+            .add_startup_system(setup.system())
+            .add_system(tray_system.system());
+    }
+}
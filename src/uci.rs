@@ -0,0 +1,190 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, Deadlines};
+use crate::controller::DEFAULT_THREADS;
+use crate::fen::parse_fen;
+use crate::rules::{LogicChessPiece, PieceColor, chess_move_to_uci, find_uci_move, initial_board, make_move};
+use crate::search::{DEFAULT_HASH_MB, MAX_DEPTH, Searcher};
+
+const MAX_HASH_MB: usize = 4096;
+const MAX_THREADS: usize = 256;
+
+type Position = (Vec<Vec<Option<LogicChessPiece>>>, Vec<Vec<bool>>, PieceColor);
+
+// `startpos` or `fen` with its six fields, either followed by `moves`
+fn parse_position(args: &[&str]) -> Result<Position, String> {
+    let (mut board, mut initial_pos, mut color) = match args.first() {
+        Some(&"startpos") => (initial_board(), vec![vec![true; 8]; 8], PieceColor::White),
+        Some(&"fen") => {
+            let fen: Vec<&str> = args[1..].iter().take_while(|arg| **arg != "moves").cloned().collect();
+            let position = parse_fen(&fen.join(" "))?;
+
+            (position.board, position.initial_pos, position.current_move)
+        },
+        _ => return Err("missing startpos or fen".to_string())
+    };
+
+    for uci in args.iter().skip_while(|arg| **arg != "moves").skip(1) {
+        let chess_move = find_uci_move(&board, &initial_pos, color, uci)
+            .ok_or(format!("illegal move {}", uci))?;
+
+        make_move(&mut board, &mut initial_pos, &chess_move);
+        color = if let PieceColor::White = color { PieceColor::Black } else { PieceColor::White };
+    }
+
+    Ok((board, initial_pos, color))
+}
+
+// The depth and the deadlines of a `go` command, without limits the search goes on until `stop`
+fn parse_go(args: &[&str], position: &Position, start: Instant) -> (u32, Option<Deadlines>) {
+    let value = |name: &str| {
+        args.iter().position(|arg| *arg == name)
+            .and_then(|i| args.get(i + 1))
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    let (board, _, color) = position;
+    let depth = value("depth").map_or(MAX_DEPTH, |depth| depth as u32);
+
+    if let Some(movetime) = value("movetime") {
+        let deadline = start + Duration::from_millis(movetime);
+        return (depth, Some(Deadlines { soft: deadline, hard: deadline }));
+    }
+
+    let (time, increment) = match color {
+        PieceColor::White => ("wtime", "winc"),
+        PieceColor::Black => ("btime", "binc")
+    };
+
+    match value(time) {
+        Some(remaining) => {
+            let clock = Clock {
+                remaining: Duration::from_millis(remaining),
+                increment: Duration::from_millis(value(increment).unwrap_or(0)),
+                moves_to_go: value("movestogo").map(|moves| moves as u32)
+            };
+
+            (depth, Some(clock.deadlines(board, start)))
+        },
+        None => (depth, None)
+    }
+}
+
+// `name Hash value 64` as a name and a value, option names aren't case sensitive
+fn parse_option(args: &[&str]) -> Option<(String, String)> {
+    let value = args.iter().position(|arg| *arg == "value")?;
+
+    if args.first() != Some(&"name") {
+        return None;
+    }
+
+    Some((args[1..value].join(" ").to_lowercase(), args[value + 1..].join(" ")))
+}
+
+// Speaks UCI on the standard input and output, so other programs can use the built-in engine
+pub fn run() -> i32 {
+    let (mut hash_mb, mut threads) = (DEFAULT_HASH_MB, DEFAULT_THREADS);
+    let searcher = Arc::new(Mutex::new(Searcher::new(hash_mb)));
+    let mut position = parse_position(&["startpos"]).unwrap();
+    let mut running: Option<(Arc<AtomicBool>, JoinHandle<()>)> = None;
+
+    let stdin = io::stdin();
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue
+        };
+
+        // Everything but isready waits for a running search to end, stop, quit and another go end it first
+        if command != "isready" {
+            if let Some((stop, thread)) = running.take() {
+                if let "stop" | "quit" | "go" = command {
+                    stop.store(true, Ordering::Relaxed);
+                }
+
+                let _ = thread.join();
+            }
+        }
+
+        match command {
+            "uci" => {
+                println!("id name rusty_chess");
+                println!("id author Miłosz Kolenderski");
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB);
+                println!("option name Threads type spin default {} min 1 max {}", DEFAULT_THREADS, MAX_THREADS);
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "setoption" => match parse_option(args) {
+                Some((name, value)) if name == "hash" => match value.parse::<usize>() {
+                    Ok(megabytes) => {
+                        hash_mb = megabytes.clamp(1, MAX_HASH_MB);
+
+                        let mut searcher = searcher.lock().unwrap();
+                        *searcher = Searcher::new(hash_mb);
+                        searcher.set_threads(threads, None);
+                    },
+                    Err(_) => println!("info string invalid Hash {}", value)
+                },
+                Some((name, value)) if name == "threads" => match value.parse::<usize>() {
+                    Ok(count) => {
+                        threads = count.clamp(1, MAX_THREADS);
+                        searcher.lock().unwrap().set_threads(threads, None);
+                    },
+                    Err(_) => println!("info string invalid Threads {}", value)
+                },
+                Some((name, _)) => println!("info string unknown option {}", name),
+                None => println!("info string malformed setoption")
+            },
+            "ucinewgame" => searcher.lock().unwrap().clear(),
+            "position" => match parse_position(args) {
+                Ok(parsed) => position = parsed,
+                Err(e) => println!("info string {}", e)
+            },
+            "go" => {
+                let start = Instant::now();
+                let (depth, deadlines) = parse_go(args, &position, start);
+                let stop = Arc::new(AtomicBool::new(false));
+                let (searcher, stopped, position) = (searcher.clone(), stop.clone(), position.clone());
+
+                let thread = thread::spawn(move || {
+                    let (board, initial_pos, color) = position;
+                    let mut searcher = searcher.lock().unwrap();
+
+                    searcher.stop = Some(stopped);
+                    searcher.deadlines = deadlines;
+
+                    let best = searcher.iterate(&board, &initial_pos, color, depth, &[],
+                                                &mut |info| println!("{}", info.uci()));
+
+                    // A mated or stalemated side has no move, UCI's null move says so
+                    match best {
+                        Some(info) => println!("bestmove {}", chess_move_to_uci(&info.pv[0])),
+                        None => println!("bestmove 0000")
+                    }
+                });
+
+                running = Some((stop, thread));
+            },
+            "quit" => break,
+            // Unknown commands are ignored, as UCI asks
+            _ => ()
+        }
+    }
+
+    if let Some((stop, thread)) = running.take() {
+        stop.store(true, Ordering::Relaxed);
+        let _ = thread.join();
+    }
+
+    0
+}